@@ -0,0 +1,297 @@
+use crate::{
+    space::{christoffel_from_derivatives, invert_metric, CoordinateSystem, Metric},
+    tensor::{Christoffel, Matrix4, Vector4},
+};
+use std::fmt::Debug;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// Forward-mode dual number carrying the value of an expression together with
+/// its partial derivatives with respect to the four coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dual {
+    pub value: f64,
+    pub gradient: [f64; 4],
+}
+
+impl Dual {
+    pub fn constant(value: f64) -> Self {
+        Dual {
+            value,
+            gradient: [0.; 4],
+        }
+    }
+
+    /// The coordinate x^index itself, whose only non-zero partial derivative
+    /// is d x^index / d x^index = 1.
+    pub fn variable(value: f64, index: usize) -> Self {
+        let mut gradient = [0.; 4];
+        gradient[index] = 1.;
+        Dual { value, gradient }
+    }
+
+    // Applies a function of known derivative with the chain rule
+    fn chain(self, value: f64, derivative: f64) -> Self {
+        Dual {
+            value,
+            gradient: self.gradient.map(|g| g * derivative),
+        }
+    }
+}
+
+impl From<f64> for Dual {
+    fn from(value: f64) -> Self {
+        Dual::constant(value)
+    }
+}
+
+impl Add for Dual {
+    type Output = Dual;
+    fn add(self, other: Dual) -> Dual {
+        let mut gradient = self.gradient;
+        for (g, o) in gradient.iter_mut().zip(other.gradient) {
+            *g += o;
+        }
+        Dual {
+            value: self.value + other.value,
+            gradient,
+        }
+    }
+}
+
+impl Sub for Dual {
+    type Output = Dual;
+    fn sub(self, other: Dual) -> Dual {
+        self + (-other)
+    }
+}
+
+impl Mul for Dual {
+    type Output = Dual;
+    fn mul(self, other: Dual) -> Dual {
+        let mut gradient = self.gradient;
+        for (g, o) in gradient.iter_mut().zip(other.gradient) {
+            *g = *g * other.value + self.value * o;
+        }
+        Dual {
+            value: self.value * other.value,
+            gradient,
+        }
+    }
+}
+
+impl Div for Dual {
+    type Output = Dual;
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn div(self, other: Dual) -> Dual {
+        self * other.recip()
+    }
+}
+
+impl Neg for Dual {
+    type Output = Dual;
+    fn neg(self) -> Dual {
+        self.chain(-self.value, -1.)
+    }
+}
+
+impl Add<f64> for Dual {
+    type Output = Dual;
+    fn add(self, other: f64) -> Dual {
+        self.chain(self.value + other, 1.)
+    }
+}
+
+impl Sub<f64> for Dual {
+    type Output = Dual;
+    fn sub(self, other: f64) -> Dual {
+        self.chain(self.value - other, 1.)
+    }
+}
+
+impl Mul<f64> for Dual {
+    type Output = Dual;
+    fn mul(self, other: f64) -> Dual {
+        self.chain(self.value * other, other)
+    }
+}
+
+impl Div<f64> for Dual {
+    type Output = Dual;
+    fn div(self, other: f64) -> Dual {
+        self.chain(self.value / other, 1. / other)
+    }
+}
+
+/// Number type a metric can be evaluated with: plain `f64` for its value, or
+/// `Dual` for its value and first derivatives at once.
+pub trait Scalar:
+    Copy
+    + Debug
+    + From<f64>
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + Add<f64, Output = Self>
+    + Sub<f64, Output = Self>
+    + Mul<f64, Output = Self>
+    + Div<f64, Output = Self>
+{
+    fn value(self) -> f64;
+    fn recip(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn powf(self, n: f64) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+}
+
+impl Scalar for f64 {
+    fn value(self) -> f64 {
+        self
+    }
+    fn recip(self) -> Self {
+        f64::recip(self)
+    }
+    fn sqrt(self) -> Self {
+        f64::sqrt(self)
+    }
+    fn powi(self, n: i32) -> Self {
+        f64::powi(self, n)
+    }
+    fn powf(self, n: f64) -> Self {
+        f64::powf(self, n)
+    }
+    fn exp(self) -> Self {
+        f64::exp(self)
+    }
+    fn ln(self) -> Self {
+        f64::ln(self)
+    }
+    fn sin(self) -> Self {
+        f64::sin(self)
+    }
+    fn cos(self) -> Self {
+        f64::cos(self)
+    }
+    fn tan(self) -> Self {
+        f64::tan(self)
+    }
+}
+
+impl Scalar for Dual {
+    fn value(self) -> f64 {
+        self.value
+    }
+    fn recip(self) -> Self {
+        self.chain(1. / self.value, -1. / (self.value * self.value))
+    }
+    fn sqrt(self) -> Self {
+        let value = self.value.sqrt();
+        self.chain(value, 0.5 / value)
+    }
+    fn powi(self, n: i32) -> Self {
+        self.chain(self.value.powi(n), f64::from(n) * self.value.powi(n - 1))
+    }
+    fn powf(self, n: f64) -> Self {
+        self.chain(self.value.powf(n), n * self.value.powf(n - 1.))
+    }
+    fn exp(self) -> Self {
+        let value = self.value.exp();
+        self.chain(value, value)
+    }
+    fn ln(self) -> Self {
+        self.chain(self.value.ln(), 1. / self.value)
+    }
+    fn sin(self) -> Self {
+        self.chain(self.value.sin(), self.value.cos())
+    }
+    fn cos(self) -> Self {
+        self.chain(self.value.cos(), -self.value.sin())
+    }
+    fn tan(self) -> Self {
+        let value = self.value.tan();
+        self.chain(value, 1. + value * value)
+    }
+}
+
+/// A metric given only by its components g_{mu nu}(x), written once for any
+/// `Scalar` so that it can be differentiated automatically.
+pub trait MetricFunction: Debug + Clone + Send + Sync {
+    fn evaluate<T: Scalar>(&self, position: &[T; 4]) -> [[T; 4]; 4];
+}
+
+/// Spacetime whose inverse metric and Christoffel symbols are derived from a
+/// `MetricFunction`, the latter by forward-mode automatic differentiation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoDiffMetric<F> {
+    pub function: F,
+    pub horizon_radius: Option<f64>,
+    pub coordinate_system: CoordinateSystem,
+}
+
+impl<F: MetricFunction> Metric for AutoDiffMetric<F> {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        Matrix4(self.function.evaluate(&position.0))
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        invert_metric(&self.components(position))
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
+        automatic_christoffel(&self.function, position)
+    }
+
+    fn horizon_radius(&self) -> Option<f64> {
+        self.horizon_radius
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        self.coordinate_system
+    }
+}
+
+/// Christoffel symbols of a `MetricFunction`, whose partial derivatives are
+/// obtained by evaluating it once with dual numbers.
+pub fn automatic_christoffel<F: MetricFunction>(function: &F, position: &Vector4) -> Christoffel {
+    let g = function.evaluate(&[
+        Dual::variable(position[0], 0),
+        Dual::variable(position[1], 1),
+        Dual::variable(position[2], 2),
+        Dual::variable(position[3], 3),
+    ]);
+    let values = Matrix4::from_fn(|i, j| g[i][j].value);
+    let derivative = |l: usize| Matrix4::from_fn(|i, j| g[i][j].gradient[l]);
+    christoffel_from_derivatives(
+        &invert_metric(&values),
+        &[derivative(0), derivative(1), derivative(2), derivative(3)],
+    )
+}
+
+/// Largest difference between the Christoffel symbols of two metrics at the
+/// given position, e.g. to check hand-written symbols against the
+/// `AutoDiffMetric` built from the same components.
+pub fn christoffel_error<M: Metric, N: Metric>(
+    metric: &M,
+    reference: &N,
+    position: &Vector4,
+) -> f64 {
+    let (christoffel, expected) = (
+        metric.christoffel(position),
+        reference.christoffel(position),
+    );
+    let mut error: f64 = 0.;
+    for i in 0..4 {
+        for j in 0..4 {
+            for k in 0..4 {
+                error = error.max((christoffel[[i, j, k]] - expected[[i, j, k]]).abs());
+            }
+        }
+    }
+    error
+}
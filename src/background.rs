@@ -0,0 +1,269 @@
+use crate::{obstacle::blackbody, tensor::Vector4};
+use image::{codecs::hdr::HdrDecoder, ImageResult, Rgb, RgbImage};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{collections::HashMap, f64::consts::PI, fs::File, io::BufReader, path::Path};
+
+/// Colour seen by the rays leaving the scene through the 'MaxDistance' or
+/// 'BigBang' obstacles. Currently, the implemented types are:
+/// - Uniform: The same color in every direction.
+/// - TwoSided: A separate background on each side of a wormhole throat,
+///   picked from the sign of the radial coordinate where the ray escapes.
+/// - Sky: A panorama of the celestial sphere, looked up in the direction in
+///   which the ray escapes, so that it shows the lensing of the sky.
+/// - Stars: A procedural catalogue of point stars, see `Starfield`.
+/// - Checkerboard: Squares of 'size' radians in theta and phi, alternating
+///   between the two colors. 'size' should divide PI.
+/// - Grid: Lines of 'width' radians every 'spacing' radians of latitude and
+///   longitude, on a black sky. They make the lensing distortion obvious.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    Uniform(Rgb<f64>),
+    TwoSided {
+        positive: Box<Background>,
+        negative: Box<Background>,
+    },
+    Sky(SkyTexture),
+    Stars(Starfield),
+    Checkerboard {
+        size: f64,
+        colors: [Rgb<f64>; 2],
+    },
+    Grid {
+        spacing: f64,
+        width: f64,
+        color: Rgb<f64>,
+    },
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Uniform(Rgb::<f64>([0., 0., 0.]))
+    }
+}
+
+impl Background {
+    /// Color of the ray escaping at 'ray_pos' (t, r, theta, phi) in the
+    /// direction 'direction' (theta, phi), see `escape_direction`.
+    pub fn color(&self, ray_pos: &Vector4, direction: &[f64; 2]) -> Rgb<f64> {
+        match self {
+            Background::Uniform(color) => *color,
+            Background::TwoSided { positive, negative } => {
+                if ray_pos[1] >= 0. {
+                    positive.color(ray_pos, direction)
+                } else {
+                    negative.color(ray_pos, direction)
+                }
+            }
+            Background::Sky(texture) => texture.color(direction[0], direction[1]),
+            Background::Stars(starfield) => starfield.color(direction[0], direction[1]),
+            Background::Checkerboard { size, colors } => {
+                let square = (direction[0] / size).floor() + (direction[1] / size).floor();
+                colors[(square as i64).rem_euclid(2) as usize]
+            }
+            Background::Grid {
+                spacing,
+                width,
+                color,
+            } => {
+                // Angular distance to the nearest line of latitude and of
+                // longitude
+                let distance = |angle: f64| (angle - (angle / spacing).round() * spacing).abs();
+                if distance(direction[0]) < width / 2.
+                    || distance(direction[1]) * direction[0].sin() < width / 2.
+                {
+                    *color
+                } else {
+                    Rgb([0., 0., 0.])
+                }
+            }
+        }
+    }
+}
+
+/// Catalogue of point stars, drawn from the seed 'seed' in directions
+/// uniformly distributed over the sky. Their magnitudes range from 0 to 6,
+/// like the stars visible to the naked eye, with ten times more stars for
+/// every two magnitudes, and their colors are those of black bodies from
+/// 3000 to 12000 Kelvin. A star is a gaussian spot of standard deviation
+/// 'size' radians, whose peak color is 255 for the magnitude 0, and 2.512
+/// times fainter for every magnitude.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Starfield {
+    pub stars: Vec<Star>,
+    pub size: f64,
+    cells: HashMap<[i64; 3], Vec<usize>>, // stars by cube of side `Starfield::cell`
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Star {
+    pub direction: [f64; 3], // unit vector
+    pub magnitude: f64,
+    pub color: Rgb<f64>, // at the center of the spot
+}
+
+impl Starfield {
+    pub fn new(seed: u64, number_stars: usize, size: f64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let stars: Vec<Star> = (0..number_stars)
+            .map(|_| {
+                let z: f64 = rng.gen_range(-1.0..1.0);
+                let phi: f64 = rng.gen_range(-PI..PI);
+                let magnitude = 6. + 2. * rng.gen_range(1e-3..1.0_f64).log10();
+                let temperature = rng.gen_range(3000.0..12000.0);
+                let brightness = 255. * 10_f64.powf(-0.4 * magnitude);
+                let horizontal = (1. - z * z).sqrt();
+                Star {
+                    direction: [horizontal * phi.cos(), horizontal * phi.sin(), z],
+                    magnitude,
+                    color: Rgb(blackbody(temperature).map(|c| c * brightness)),
+                }
+            })
+            .collect();
+        let mut starfield = Starfield {
+            stars,
+            size,
+            cells: HashMap::new(),
+        };
+        for (index, star) in starfield.stars.iter().enumerate() {
+            let cell = starfield.cell(&star.direction);
+            starfield.cells.entry(cell).or_default().push(index);
+        }
+        starfield
+    }
+
+    /// Sum of the spots of the stars in the direction (theta, phi), which
+    /// are cut beyond 4 times their size.
+    pub fn color(&self, theta: f64, phi: f64) -> Rgb<f64> {
+        let direction = [
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        ];
+        let [x, y, z] = self.cell(&direction);
+        let mut color = [0.; 3];
+        for neighbour in (0..27).map(|i| [x + i % 3 - 1, y + i / 3 % 3 - 1, z + i / 9 - 1]) {
+            for star in self.cells.get(&neighbour).into_iter().flatten() {
+                let star = &self.stars[*star];
+                // Chord between the two directions, close to the angle
+                let distance = (0..3)
+                    .map(|i| (direction[i] - star.direction[i]).powi(2))
+                    .sum::<f64>();
+                if distance < 16. * self.size * self.size {
+                    let weight = (-distance / (2. * self.size * self.size)).exp();
+                    for (c, star_color) in color.iter_mut().zip(star.color.0) {
+                        *c += weight * star_color;
+                    }
+                }
+            }
+        }
+        Rgb(color)
+    }
+
+    // Cube of the unit vector 'direction', of side 4 times the size of the
+    // stars, so that the stars seen in a direction lie in the neighbouring
+    // cubes of its own.
+    fn cell(&self, direction: &[f64; 3]) -> [i64; 3] {
+        direction.map(|x| (x / (4. * self.size)).floor() as i64)
+    }
+}
+
+/// Panorama of the whole sky in the equirectangular projection: its columns
+/// go from the longitude phi = -PI on the left edge to PI on the right edge,
+/// its rows from the north pole theta = 0 at the top to the south pole at
+/// the bottom. The colors range from 0 to 255, like those of the obstacles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkyTexture {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Rgb<f64>>, // row by row, from the top left corner
+}
+
+impl SkyTexture {
+    pub fn new(image: &RgbImage) -> Self {
+        SkyTexture {
+            width: image.width(),
+            height: image.height(),
+            pixels: image
+                .pixels()
+                .map(|pixel| Rgb(pixel.0.map(f64::from)))
+                .collect(),
+        }
+    }
+
+    /// Loads the panorama at 'path'. Radiance (.hdr) files keep their high
+    /// dynamic range, a radiance of 1 giving the color 255.
+    pub fn open<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        if extension.as_deref() == Some("hdr") {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()?
+                .iter()
+                .map(|pixel| Rgb(pixel.0.map(|channel| 255. * channel as f64)))
+                .collect();
+            return Ok(SkyTexture {
+                width: metadata.width,
+                height: metadata.height,
+                pixels,
+            });
+        }
+        Ok(SkyTexture::new(&image::open(path)?.to_rgb8()))
+    }
+
+    /// Color in the direction (theta, phi), interpolated bilinearly between
+    /// the four nearest pixel centers. The longitude wraps around.
+    pub fn color(&self, theta: f64, phi: f64) -> Rgb<f64> {
+        let (width, height) = (self.width as i64, self.height as i64);
+        let x = ((phi + PI) / (2. * PI)).rem_euclid(1.) * width as f64 - 0.5;
+        let y = (theta / PI).clamp(0., 1.) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let pixel = |i: i64, j: i64| {
+            let i = i.rem_euclid(width);
+            let j = j.clamp(0, height - 1);
+            self.pixels[(j * width + i) as usize]
+        };
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let corners = [
+            (pixel(x0, y0), (1. - fx) * (1. - fy)),
+            (pixel(x0 + 1, y0), fx * (1. - fy)),
+            (pixel(x0, y0 + 1), (1. - fx) * fy),
+            (pixel(x0 + 1, y0 + 1), fx * fy),
+        ];
+        Rgb([0, 1, 2].map(|c| {
+            corners
+                .iter()
+                .map(|(color, weight)| color[c] * weight)
+                .sum()
+        }))
+    }
+}
+
+/// Direction (theta, phi) in which a ray moves from 'from' to 'to', given in
+/// (t, r, theta, phi) coordinates, as seen from afar: where it meets the
+/// celestial sphere once it has escaped.
+pub fn escape_direction(from: &Vector4, to: &Vector4) -> [f64; 2] {
+    let cartesian = |position: &Vector4| {
+        let (r, theta, phi) = (position[1].abs(), position[2], position[3]);
+        [
+            r * theta.sin() * phi.cos(),
+            r * theta.sin() * phi.sin(),
+            r * theta.cos(),
+        ]
+    };
+    let (start, end) = (cartesian(from), cartesian(to));
+    let chord = [0, 1, 2].map(|i| end[i] - start[i]);
+    let length = (chord[0].powi(2) + chord[1].powi(2) + chord[2].powi(2)).sqrt();
+    if length == 0. {
+        return [to[2], to[3]];
+    }
+    [
+        (chord[2] / length).clamp(-1., 1.).acos(),
+        chord[1].atan2(chord[0]),
+    ]
+}
@@ -1,8 +1,23 @@
 use std::{error, f64::consts::PI};
 
 use clap::{self, Arg, Command};
-use ndarray::{Array1, Array3};
-use relativistic_ray_tracing::{camera::Camera, obstacle::Obstacle, space::Space};
+use image::Rgb;
+use ndarray::Array1;
+use relativistic_ray_tracing::{
+    background::{Background, SkyTexture, Starfield},
+    camera::Camera,
+    deflection::DeflectionTable,
+    framebuffer::Framebuffer,
+    integrator::{DormandPrince, Hamiltonian, Integrator, Rk4, StormerVerlet},
+    observer::Observer,
+    obstacle::Obstacle,
+    scene::{Redshift, Scene},
+    space::{
+        EllisWormhole, Flrw, Kerr, KerrNewman, KerrSchild, Metric, Minkowski, ReissnerNordstrom,
+        Schwarzschild, SchwarzschildDeSitter,
+    },
+    tonemap::{Exposure, Operator, ToneMapping},
+};
 fn main() -> Result<(), Box<dyn error::Error>> {
     let matches = app().get_matches();
     let black_hole_radius = match matches.value_of("black_hole_radius") {
@@ -26,12 +41,109 @@ fn main() -> Result<(), Box<dyn error::Error>> {
             Err(_) => panic!("Could not parse image width {} to u32!", w),
         },
     };
+    let metric = matches.value_of("metric").unwrap_or("schwarzschild");
+    let spin = match matches.value_of("spin") {
+        None => 0.,
+        Some(a) => match a.parse::<f64>() {
+            Ok(a) => a,
+            Err(_) => panic!("Could not parse spin {} to f64!", a),
+        },
+    };
+    let charge = match matches.value_of("charge") {
+        None => 0.,
+        Some(q) => match q.parse::<f64>() {
+            Ok(q) => q,
+            Err(_) => panic!("Could not parse charge {} to f64!", q),
+        },
+    };
+    let cosmological_constant = match matches.value_of("cosmological_constant") {
+        None => 1e-3,
+        Some(c) => match c.parse::<f64>() {
+            Ok(c) => c,
+            Err(_) => panic!("Could not parse cosmological constant {} to f64!", c),
+        },
+    };
+    let hubble_constant = match matches.value_of("hubble_constant") {
+        None => 5e-3,
+        Some(h) => match h.parse::<f64>() {
+            Ok(h) => h,
+            Err(_) => panic!("Could not parse Hubble constant {} to f64!", h),
+        },
+    };
+    let [pitch, yaw, roll] = ["pitch", "yaw", "roll"].map(|angle| match matches.value_of(angle) {
+        None => 0.,
+        Some(a) => match a.parse::<f64>() {
+            Ok(a) => a.to_radians(),
+            Err(_) => panic!("Could not parse {} {} to f64!", angle, a),
+        },
+    });
+    let integrator = matches.value_of("integrator").unwrap_or("rk4");
+    let output = matches.value_of("output").unwrap_or("render.png");
+    let operator = match matches.value_of("tone_mapping").unwrap_or("linear") {
+        "linear" => Operator::Linear,
+        "reinhard" => Operator::Reinhard,
+        "aces" => Operator::Aces,
+        "log" => Operator::Logarithmic,
+        t => panic!("Unknown tone mapping {}!", t),
+    };
+    let exposure = match matches.value_of("exposure") {
+        None => ToneMapping::default().exposure,
+        Some("auto") => Exposure::Percentile {
+            percentile: 0.99,
+            key: 1.,
+        },
+        Some(e) => match e.parse::<f64>() {
+            Ok(e) => Exposure::Fixed(e),
+            Err(_) => panic!("Could not parse exposure {} to f64!", e),
+        },
+    };
+    let tone_mapping = ToneMapping {
+        operator,
+        exposure,
+        ..ToneMapping::default()
+    };
+    let observer = match matches.value_of("observer").unwrap_or("zamo") {
+        "static" => Observer::Static,
+        "zamo" => Observer::Zamo,
+        "free-fall" => Observer::FreeFall,
+        "orbit" => Observer::CircularOrbit { prograde: true },
+        o => panic!("Unknown observer {}!", o),
+    };
+    let redshift = match matches.value_of("redshift").unwrap_or("off") {
+        "off" => Redshift::Off,
+        "specific" => Redshift::Specific,
+        "bolometric" => Redshift::Bolometric,
+        z => panic!("Unknown redshift {}!", z),
+    };
+    let deflection_angles =
+        matches
+            .value_of("deflection_table")
+            .map(|d| match d.parse::<usize>() {
+                Ok(d) if d >= 2 => d,
+                Ok(d) => panic!("Deflection tables need at least 2 rays, not {}!", d),
+                Err(_) => panic!("Could not parse deflection table size {} to usize!", d),
+            });
     println!("Black hole radius {}", black_hole_radius);
     println!("Image length {}", image_length);
     println!("Image width {}", image_width);
     let camera_distance = 30. * black_hole_radius;
-    let blackhole = Obstacle::BlackHole {
-        r: black_hole_radius,
+    let fov = [PI / 2.5, PI / 5.];
+    // Angle covered by a pixel, the size of the stars and the width of the
+    // grid lines
+    let pixel_angle = fov[0] / image_length as f64;
+    let background = match matches.value_of("sky") {
+        None => Background::default(),
+        Some("stars") => Background::Stars(Starfield::new(0, 10000, pixel_angle)),
+        Some("checkerboard") => Background::Checkerboard {
+            size: PI / 18.,
+            colors: [Rgb([200., 200., 200.]), Rgb([60., 60., 60.])],
+        },
+        Some("grid") => Background::Grid {
+            spacing: PI / 18.,
+            width: pixel_angle,
+            color: Rgb([200., 200., 200.]),
+        },
+        Some(path) => Background::Sky(SkyTexture::open(path)?),
     };
     let _blackholepred = Obstacle::BlackHolePredict {
         r: black_hole_radius,
@@ -50,30 +162,271 @@ fn main() -> Result<(), Box<dyn error::Error>> {
     let max_radius = Obstacle::MaxDistance {
         r: camera_distance * 1.1,
     };
-    let mut space = Space {
-        rs: 100.0,
-        c: 1.0,
-        christoffel: Array3::zeros((4, 4, 4)),
-        obstacles: Vec::from([blackhole, max_radius, accretion_disk]),
-    };
+    let obstacles = Vec::from([accretion_disk]);
 
     let mut cam_position = Array1::<f64>::zeros(3);
     cam_position[0] = camera_distance;
     cam_position[1] = PI * 0.455;
     let mut cam_orientation = Array1::<f64>::zeros(3);
-    cam_orientation[0] = 0.;
-    cam_orientation[1] = 0.;
+    cam_orientation[0] = pitch;
+    cam_orientation[1] = yaw;
+    cam_orientation[2] = roll;
 
     let camera = Camera {
-        fov: [PI / 2.5, PI / 5.],
+        fov,
         im_size: [image_length, image_width],
         orientation: cam_orientation,
         position: cam_position,
+        observer,
     };
-    camera.render(4, 1000, 40., &mut space, 2.5, 0.75);
+    let framebuffer = match metric {
+        "schwarzschild" if integrator == "elliptic" => render_photon_orbits(
+            Schwarzschild {
+                rs: black_hole_radius,
+                c: 1.0,
+            },
+            obstacles,
+            max_radius,
+            background,
+            redshift,
+            deflection_angles.unwrap_or(2048),
+            &camera,
+            &tone_mapping,
+        ),
+        "schwarzschild" => render_scene(
+            Schwarzschild {
+                rs: black_hole_radius,
+                c: 1.0,
+            },
+            obstacles,
+            max_radius,
+            background,
+            redshift,
+            integrator,
+            deflection_angles,
+            &camera,
+            &tone_mapping,
+        ),
+        "kerr" => render_scene(
+            Kerr {
+                rs: black_hole_radius,
+                a: spin * black_hole_radius / 2.,
+            },
+            obstacles,
+            max_radius,
+            background,
+            redshift,
+            integrator,
+            deflection_angles,
+            &camera,
+            &tone_mapping,
+        ),
+        "kerr-schild" => render_scene(
+            KerrSchild {
+                rs: black_hole_radius,
+                a: spin * black_hole_radius / 2.,
+            },
+            obstacles,
+            max_radius,
+            background,
+            redshift,
+            integrator,
+            deflection_angles,
+            &camera,
+            &tone_mapping,
+        ),
+        "reissner-nordstrom" => render_scene(
+            ReissnerNordstrom {
+                rs: black_hole_radius,
+                q: charge * black_hole_radius / 2.,
+            },
+            obstacles,
+            max_radius,
+            background,
+            redshift,
+            integrator,
+            deflection_angles,
+            &camera,
+            &tone_mapping,
+        ),
+        "kerr-newman" => render_scene(
+            KerrNewman {
+                rs: black_hole_radius,
+                a: spin * black_hole_radius / 2.,
+                q: charge * black_hole_radius / 2.,
+            },
+            obstacles,
+            max_radius,
+            background,
+            redshift,
+            integrator,
+            deflection_angles,
+            &camera,
+            &tone_mapping,
+        ),
+        "minkowski" => render_scene(
+            Minkowski,
+            obstacles,
+            max_radius,
+            background,
+            redshift,
+            integrator,
+            deflection_angles,
+            &camera,
+            &tone_mapping,
+        ),
+        "schwarzschild-de-sitter" => render_scene(
+            SchwarzschildDeSitter {
+                rs: black_hole_radius,
+                lambda: cosmological_constant / black_hole_radius.powi(2),
+            },
+            obstacles,
+            max_radius,
+            background,
+            redshift,
+            integrator,
+            deflection_angles,
+            &camera,
+            &tone_mapping,
+        ),
+        "flrw" => render_scene(
+            Flrw {
+                hubble_constant: hubble_constant / black_hole_radius,
+                w: 0.,
+            },
+            obstacles,
+            max_radius,
+            background,
+            redshift,
+            integrator,
+            deflection_angles,
+            &camera,
+            &tone_mapping,
+        ),
+        "ellis-wormhole" => render_scene(
+            EllisWormhole {
+                throat_radius: black_hole_radius,
+            },
+            obstacles,
+            max_radius,
+            Background::TwoSided {
+                positive: Box::new(background),
+                negative: Box::new(Background::Uniform(Rgb::<f64>([40., 70., 160.]))),
+            },
+            redshift,
+            integrator,
+            deflection_angles,
+            &camera,
+            &tone_mapping,
+        ),
+        _ => panic!("Unknown metric {}!", metric),
+    };
+    framebuffer.save(output)?;
     Ok(())
 }
 
+fn build_scene<M: Metric>(
+    metric: &M,
+    mut obstacles: Vec<Obstacle>,
+    max_radius: Obstacle,
+    background: Background,
+    redshift: Redshift,
+) -> Scene {
+    // Nothing can be seen beyond a cosmological horizon
+    obstacles.insert(
+        0,
+        Obstacle::cosmological_horizon(metric).unwrap_or(max_radius),
+    );
+    if let Some(blackhole) = Obstacle::black_hole(metric) {
+        obstacles.insert(0, blackhole);
+    }
+    Scene {
+        obstacles,
+        background,
+        redshift,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_photon_orbits(
+    metric: Schwarzschild,
+    obstacles: Vec<Obstacle>,
+    max_radius: Obstacle,
+    background: Background,
+    redshift: Redshift,
+    number_angles: usize,
+    camera: &Camera,
+    tone_mapping: &ToneMapping,
+) -> Framebuffer {
+    let scene = build_scene(&metric, obstacles, max_radius, background, redshift);
+    let table = DeflectionTable::from_photon_orbits(
+        &metric,
+        &scene,
+        camera.position[0],
+        number_angles,
+        PI / 128.,
+    );
+    camera.render_deflection_table(4, &metric, &table, &scene, tone_mapping)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_scene<M: Metric>(
+    metric: M,
+    obstacles: Vec<Obstacle>,
+    max_radius: Obstacle,
+    background: Background,
+    redshift: Redshift,
+    integrator: &str,
+    deflection_angles: Option<usize>,
+    camera: &Camera,
+    tone_mapping: &ToneMapping,
+) -> Framebuffer {
+    let integrator: Box<dyn Integrator<M>> = match integrator {
+        "rk4" => Box::new(Rk4 {
+            adaptive_step: true,
+        }),
+        "rk45" => Box::new(DormandPrince {
+            absolute_tolerance: 1e-6,
+            relative_tolerance: 1e-6,
+        }),
+        "verlet" => Box::new(StormerVerlet {
+            adaptive_step: true,
+        }),
+        "hamiltonian" => Box::new(Hamiltonian {
+            adaptive_step: true,
+            projection: true,
+        }),
+        "elliptic" => panic!("Closed form orbits are only available in Schwarzschild!"),
+        _ => panic!("Unknown integrator {}!", integrator),
+    };
+    let scene = build_scene(&metric, obstacles, max_radius, background, redshift);
+    if let Some(number_angles) = deflection_angles {
+        let table = DeflectionTable::new(
+            &metric,
+            &scene,
+            camera.position[0],
+            number_angles,
+            PI / 128.,
+            1000,
+            40.,
+            integrator.as_ref(),
+        )
+        .expect("Deflection tables need a static, spherically symmetric metric!");
+        return camera.render_deflection_table(4, &metric, &table, &scene, tone_mapping);
+    }
+    let (framebuffer, statistics) = camera.render(
+        4,
+        1000,
+        40.,
+        integrator.as_ref(),
+        &metric,
+        &scene,
+        tone_mapping,
+    );
+    println!("{}", statistics);
+    framebuffer
+}
+
 fn app() -> clap::Command<'static> {
     Command::new("relativistic ray tracing CLI")
         .version(clap::crate_version!())
@@ -101,6 +454,117 @@ Run the relativistic ray tracer from a command line interface.
                 .help("output image width")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("metric")
+                .short('m')
+                .help("Spacetime to trace in")
+                .possible_values([
+                    "schwarzschild",
+                    "kerr",
+                    "kerr-schild",
+                    "reissner-nordstrom",
+                    "kerr-newman",
+                    "ellis-wormhole",
+                    "minkowski",
+                    "schwarzschild-de-sitter",
+                    "flrw",
+                ])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("integrator")
+                .short('i')
+                .help("Integration scheme of the rays: adaptive RK4, Dormand-Prince RK45, symplectic Stormer-Verlet, RK4 on the covariant momenta, or closed form Schwarzschild orbits")
+                .possible_values(["rk4", "rk45", "verlet", "hamiltonian", "elliptic"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("spin")
+                .short('a')
+                .help("Dimensionless spin a/M of a Kerr black hole, between -1 and 1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("cosmological_constant")
+                .short('L')
+                .help("Cosmological constant of a de Sitter universe, in units of 1/rs^2")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("hubble_constant")
+                .short('H')
+                .help("Hubble constant of a matter dominated FLRW universe, in units of 1/rs")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("pitch")
+                .short('p')
+                .help("Angle in degrees between the line of sight of the camera and the black hole, upwards")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("yaw")
+                .short('y')
+                .help("Angle in degrees between the line of sight of the camera and the black hole, to the right")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("roll")
+                .short('r')
+                .help("Rotation in degrees of the image around the line of sight")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("deflection_table")
+                .short('d')
+                .help("Render from a table of the paths of this number of rays, at least 2, in static spherically symmetric spacetimes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("observer")
+                .short('o')
+                .help("Motion of the camera: at rest, with zero angular momentum, falling freely from infinity, or on a prograde circular orbit")
+                .possible_values(["static", "zamo", "free-fall", "orbit"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("output")
+                .short('f')
+                .help("File the image is saved to, in the format given by its extension (png, jpg, bmp...), or in floating point before tone mapping for pfm and hdr")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("tone_mapping")
+                .short('t')
+                .help("Tone mapping operator compressing the radiance into 8-bit colors")
+                .possible_values(["linear", "reinhard", "aces", "log"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("exposure")
+                .short('e')
+                .help("Factor applied to the radiance, the same for every frame, or auto to map the 99th percentile of the luminance to white (by default, the brightest pixel maps to 2.5)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("sky")
+                .short('s')
+                .help("Sky seen by the escaping rays: stars, checkerboard, grid (every 10 degrees), or the file of an equirectangular panorama, such as a Milky Way image")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("redshift")
+                .short('z')
+                .help("Shift of the light of the accretion disk by its orbital motion and gravity: none (default), on the temperature and the intensity as g^3, or as g^4, making the approaching side brighter and bluer")
+                .possible_values(["off", "specific", "bolometric"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("charge")
+                .short('q')
+                .help("Dimensionless charge Q/M of a charged black hole, between -1 and 1")
+                .takes_value(true),
+        )
 }
 #[test]
 fn verify_app() {
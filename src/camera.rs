@@ -1,17 +1,64 @@
-use crate::{ray::Ray, space::Space};
-use ang::atan2;
+use crate::{
+    deflection::DeflectionTable,
+    framebuffer::Framebuffer,
+    integrator::Integrator,
+    observer::{change_frame, Observer},
+    ray::{Ray, TraceOutcome},
+    scene::Scene,
+    space::Metric,
+    tensor::Vector4,
+    tonemap::ToneMapping,
+};
 use image::{ImageBuffer, Rgb, RgbImage};
 use ndarray::Array1;
 use num_integer::Roots;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::f64::consts::PI;
-use std::sync::atomic::AtomicUsize;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What it cost to render an image with `Camera::render`, summed over its
+/// rays: the integration scheme and its steps (see `TraceStatistics`), and
+/// how many rays ended with each `TraceOutcome`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RenderStatistics {
+    pub scheme: &'static str,
+    pub accepted_steps: usize,
+    pub rejected_steps: usize,
+    pub evaluations: usize,
+    pub hit: usize,
+    pub captured: usize,
+    pub escaped: usize,
+    pub failed: usize,
+    pub exhausted: usize,
+}
+
+impl fmt::Display for RenderStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Integrator {} : {} accepted steps, {} rejected steps, {} Christoffel evaluations",
+            self.scheme, self.accepted_steps, self.rejected_steps, self.evaluations
+        )?;
+        write!(
+            f,
+            "Rays : {} hit an obstacle, {} captured, {} escaped, {} failed, {} ran out of steps",
+            self.hit, self.captured, self.escaped, self.failed, self.exhausted
+        )
+    }
+}
+
+/// Pinhole camera carried by 'observer' at 'position'. With a zero 'orientation', it looks
+/// at the center, with the north pole up. The orientation turns its line of
+/// sight by a yaw to the right, then a pitch upwards, and rolls the image
+/// around it.
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct Camera {
     pub position: Array1<f64>,    // r, theta, phi
-    pub orientation: Array1<f64>, // theta, phi, psi
+    pub orientation: Array1<f64>, // pitch, yaw, roll
     pub im_size: [u32; 2],
     pub fov: [f64; 2],
+    pub observer: Observer,
 }
 
 impl Camera {
@@ -21,25 +68,137 @@ impl Camera {
             orientation: Array1::<f64>::zeros(3),
             im_size: [100, 100],
             fov: [PI / 4.; 2],
+            observer: Observer::default(),
         }
     }
 
-    pub fn render(
+    /// Traces 'n_rays' rays per pixel backwards from the camera, and returns
+    /// the image they see with the statistics of their tracing.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render<M: Metric>(
         &self,
         n_rays: usize,
         number_steps: i32,
         step_size: f64,
-        space: &mut Space,
-        exposition: f64,
-        gamma: f64,
-    ) {
+        integrator: &dyn Integrator<M>,
+        metric: &M,
+        scene: &Scene,
+        tone_mapping: &ToneMapping,
+    ) -> (Framebuffer, RenderStatistics) {
+        let accepted_steps = AtomicUsize::new(0);
+        let rejected_steps = AtomicUsize::new(0);
+        let evaluations = AtomicUsize::new(0);
+        let outcomes = [0; 5].map(AtomicUsize::new);
+        let ray_position = Vector4([0., self.position[0], self.position[1], self.position[2]]);
+        let tetrad = self.observer.tetrad(metric, &ray_position);
+        let vec_pixels = self.shade_pixels(n_rays, |ray_orientation| {
+            let mut ray = Ray::from_tetrad(&ray_position, ray_orientation, &tetrad, metric);
+            let d_lambda = step_size;
+            let result_trace =
+                ray.trace_with(metric, scene, number_steps, d_lambda, integrator, false);
+            accepted_steps.fetch_add(ray.statistics.accepted_steps, Ordering::Relaxed);
+            rejected_steps.fetch_add(ray.statistics.rejected_steps, Ordering::Relaxed);
+            evaluations.fetch_add(ray.statistics.evaluations, Ordering::Relaxed);
+            let outcome = match result_trace.outcome {
+                TraceOutcome::Hit(_) => 0,
+                TraceOutcome::Captured(_) => 1,
+                TraceOutcome::Escaped { .. } => 2,
+                TraceOutcome::NumericalFailure => 3,
+                TraceOutcome::BudgetExhausted => 4,
+            };
+            outcomes[outcome].fetch_add(1, Ordering::Relaxed);
+            outcome_color(&result_trace.outcome)
+        });
+        let [hit, captured, escaped, failed, exhausted] = outcomes.map(AtomicUsize::into_inner);
+        let statistics = RenderStatistics {
+            scheme: integrator.name(),
+            accepted_steps: accepted_steps.into_inner(),
+            rejected_steps: rejected_steps.into_inner(),
+            evaluations: evaluations.into_inner(),
+            hit,
+            captured,
+            escaped,
+            failed,
+            exhausted,
+        };
+        let framebuffer =
+            Framebuffer::new(self.im_size[0], self.im_size[1], vec_pixels, tone_mapping);
+        (framebuffer, statistics)
+    }
+
+    /// Same as `render`, with the rays replayed from a deflection table built
+    /// for the radius of the camera instead of being integrated. The
+    /// directions seen by a moving camera are converted to the frame of the
+    /// observer at rest the table was built for. Rays the table could not
+    /// resolve are blue.
+    pub fn render_deflection_table<M: Metric>(
+        &self,
+        n_rays: usize,
+        metric: &M,
+        table: &DeflectionTable,
+        scene: &Scene,
+        tone_mapping: &ToneMapping,
+    ) -> Framebuffer {
+        assert!(
+            (table.observer_radius - self.position[0]).abs() <= 1e-9 * table.observer_radius.abs(),
+            "Deflection table built for radius {} used by a camera at radius {}!",
+            table.observer_radius,
+            self.position[0]
+        );
+        let ray_position = Vector4([0., self.position[0], self.position[1], self.position[2]]);
+        let g = metric.components(&metric.from_spherical(&ray_position));
+        let tetrad = self.observer.tetrad(metric, &ray_position);
+        let rest_tetrad = Observer::Zamo.tetrad(metric, &ray_position);
+        let vec_pixels = self.shade_pixels(n_rays, |ray_orientation| {
+            let (ray_orientation, energy) =
+                change_frame(&g, &tetrad, &rest_tetrad, ray_orientation);
+            outcome_color(&table.trace(metric, &ray_position, &ray_orientation, scene, energy))
+        });
+        Framebuffer::new(self.im_size[0], self.im_size[1], vec_pixels, tone_mapping)
+    }
+
+    /// Direction (theta, phi) in the local frame of the camera, see
+    /// `Ray::new_i`, of the ray at the angular offset (x, y) from the center
+    /// of the image.
+    pub fn ray_orientation(&self, offset: [f64; 2]) -> [f64; 2] {
+        let (pitch, yaw, roll) = (
+            self.orientation[0],
+            self.orientation[1],
+            self.orientation[2],
+        );
+        // Components on (e_r, e_theta, e_phi) of the line of sight and of the
+        // right and down directions of the image, looking at the center
+        let (forward, right, down) = ([-1., 0., 0.], [0., 0., -1.], [0., 1., 0.]);
+        let turn = |u: [f64; 3], v: [f64; 3], angle: f64| {
+            let (sin, cos) = angle.sin_cos();
+            [0, 1, 2].map(|i| cos * u[i] + sin * v[i])
+        };
+        let (forward, right) = (turn(forward, right, yaw), turn(right, forward, -yaw));
+        let (forward, down) = (turn(forward, down, -pitch), turn(down, forward, pitch));
+        let radius = (offset[0].powi(2) + offset[1].powi(2)).sqrt();
+        let angle = offset[1].atan2(offset[0]) + roll;
+        let side = turn(right, down, angle);
+        let direction = turn(forward, side, radius);
+        [
+            direction[0].clamp(-1., 1.).acos(),
+            direction[2].atan2(direction[1]),
+        ]
+    }
+
+    // Sums the colors given by 'shade' for the directions of the n_rays
+    // sub-rays of each pixel, see `Ray::new_i` for the orientations.
+    fn shade_pixels<F: Fn(&[f64; 2]) -> Rgb<f64> + Sync>(
+        &self,
+        n_rays: usize,
+        shade: F,
+    ) -> Vec<Rgb<f64>> {
         let n_rays = n_rays.sqrt();
         let n_rays_float = n_rays as f64;
         let size_x = self.im_size[0];
         let size_x_float = size_x as f64;
         let size_y = self.im_size[1];
         let size_y_float = size_y as f64;
-        let mut img: RgbImage = ImageBuffer::new(self.im_size[0], self.im_size[1]);
+        let img: RgbImage = ImageBuffer::new(self.im_size[0], self.im_size[1]);
         let coordinates: Vec<(u32, u32)> = img
             .enumerate_pixels()
             .into_iter()
@@ -47,7 +206,7 @@ impl Camera {
             .collect();
         let n_steps = coordinates.len();
         let progression = AtomicUsize::new(0);
-        let vec_pixels: Vec<Rgb<f64>> = coordinates
+        coordinates
             .into_par_iter()
             .map(|(x, y)| {
                 let mut r: f64 = 0.;
@@ -63,44 +222,19 @@ impl Camera {
                             / size_y_float
                             + ((ray_y as f64) - (n_rays_float - 1.) / 2.) * self.fov[1]
                                 / (size_y_float * n_rays_float);
-                        let theta = PI + (cx.powi(2) + cy.powi(2)).sqrt();
-                        let phi = PI / 2. + self.orientation[2] + atan2(cy, cx).in_radians();
-                        if (theta - PI) == 0. {
+                        if cx == 0. && cy == 0. {
                             println!(
                                 "Zero Angle Theta: for i = {}, j = {} are {},{}",
                                 x, y, ray_x, ray_y
                             );
                         }
-                        let mut ray_position = Array1::<f64>::zeros(4);
-                        ray_position[1] = self.position[0];
-                        ray_position[2] = self.position[1];
-                        ray_position[3] = self.position[2];
-                        let mut ray_orientation = Array1::<f64>::zeros(2);
-                        ray_orientation[0] = theta;
-                        ray_orientation[1] = phi;
-
-                        let mut ray =
-                            Ray::new_i(step_size, &ray_position, &ray_orientation, 1., space);
-                        let d_lambda = step_size;
-                        let result_trace = ray.trace(space, number_steps, d_lambda, true, false);
-                        if let Some(collision) = result_trace {
-                            let rgb = collision.color;
-                            r += rgb[0] as f64;
-                            g += rgb[1] as f64;
-                            b += rgb[2] as f64;
-                        } else if f64::is_nan(ray.position[1]) {
-                            r += 255.;
-                            g += 0.;
-                            b += 0.;
-                        } else {
-                            r += 0.;
-                            g += 0.;
-                            b += 255.;
-                        }
+                        let rgb = shade(&self.ray_orientation([cx, cy]));
+                        r += rgb[0];
+                        g += rgb[1];
+                        b += rgb[2];
                     }
                 }
-                let progression_counter =
-                    progression.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let progression_counter = progression.fetch_add(1, Ordering::Relaxed);
                 if progression_counter % (n_steps / 10) == 0 {
                     println!(
                         "Progression : {:?} %",
@@ -109,22 +243,18 @@ impl Camera {
                 }
                 image::Rgb([r, g, b])
             })
-            .collect();
-        let max_value: f64 = vec_pixels
-            .iter()
-            .map(|pixel| pixel[0].max(pixel[1]).max(pixel[2]))
-            .reduce(f64::max)
-            .expect("This iterator is not empty");
-        for ((_, _, pixel_img), pixel_calculated) in img.enumerate_pixels_mut().zip(vec_pixels) {
-            let r =
-                ((pixel_calculated[0] / max_value * exposition).powf(gamma) * 255.).min(255.) as u8;
-            let g =
-                ((pixel_calculated[1] / max_value * exposition).powf(gamma) * 255.).min(255.) as u8;
-            let b =
-                ((pixel_calculated[2] / max_value * exposition).powf(gamma) * 255.).min(255.) as u8;
-            *pixel_img = Rgb::from([r, g, b]);
-        }
-        let title = "render.png";
-        img.save(title).expect("Problem on saving image");
+            .collect()
+    }
+}
+
+// Color of a ray: the color of what it met, red if its integration failed,
+// blue if it ran out of steps.
+fn outcome_color(outcome: &TraceOutcome) -> Rgb<f64> {
+    match outcome {
+        TraceOutcome::Hit(collision)
+        | TraceOutcome::Captured(collision)
+        | TraceOutcome::Escaped { collision, .. } => collision.color,
+        TraceOutcome::NumericalFailure => Rgb([255., 0., 0.]),
+        TraceOutcome::BudgetExhausted => Rgb([0., 0., 255.]),
     }
 }
@@ -0,0 +1,642 @@
+use crate::{
+    background::escape_direction,
+    integrator::Integrator,
+    obstacle::{CollisionPoint, Obstacle},
+    photon_orbit::PhotonOrbit,
+    ray::{angular_components, angular_momentum, dot, normalized, unit_vector, Ray, TraceOutcome},
+    scene::{Scene, KEPLERIAN},
+    space::{Metric, Schwarzschild},
+    tensor::Vector4,
+};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::f64::consts::PI;
+
+/// Precomputed light paths seen by an observer at rest at a given radius of a
+/// static, spherically symmetric spacetime such as Schwarzschild.
+///
+/// Every light ray then moves in a plane containing the center, and its path
+/// in that plane only depends on its emission angle: the angle between its
+/// direction and the outward radial direction, or equivalently its impact
+/// parameter. The table integrates one ray per emission angle, in the equator
+/// plane, and keeps how it ends and where it crosses the planes containing
+/// the center. Any other ray is the rotation of an interpolated path of the
+/// table into its own orbit plane, whose crossings of the equator of the
+/// scene, where the disks lie, are looked up (see `trace`) instead of being
+/// integrated.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DeflectionTable {
+    pub observer_radius: f64,
+    pub angle_step: f64,            // orbit angle between two crossings of a path
+    pub paths: Vec<DeflectionPath>, // emission angles (i + 0.5) * PI / paths.len()
+    pub boundaries: Vec<Obstacle>,  // obstacles of the scene ending the paths
+    pub disks: Vec<Obstacle>,       // other obstacles of the scene, looked up along them
+}
+
+/// Path of a ray in its orbit plane, from the observer at orbit angle 0 until
+/// it leaves the scene, falls into the black hole, or runs out of steps.
+///
+/// A plane containing the center meets the orbit plane along a line, which
+/// the ray crosses every PI of orbit angle. 'crossings' holds where it does
+/// for the planes whose line is at every multiple of `angle_step`, among
+/// which is the equator of the scene for any orbit plane, up to an angle
+/// step.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DeflectionPath {
+    pub emission_angle: f64,
+    pub impact_parameter: f64,    // L / E
+    pub crossings: Vec<[f64; 2]>, // (t, r) every `angle_step` of orbit angle
+    pub end: [f64; 3],            // (t, r, orbit angle) where the path ends
+    pub fate: Fate,
+}
+
+/// What happened to a ray of the table:
+/// - Captured: stopped by a BlackHole or BlackHolePredict obstacle.
+/// - Escaped: left the scene through a MaxDistance or BigBang obstacle. The
+///   deflection is the angle between its final and initial directions, as
+///   measured by observers at rest, positive when bent towards the center.
+/// - Unresolved: ran out of steps, or the integration failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fate {
+    Captured,
+    Escaped { deflection: f64 },
+    Unresolved,
+}
+
+impl Default for Fate {
+    fn default() -> Self {
+        Fate::Unresolved
+    }
+}
+
+impl DeflectionTable {
+    /// Integrates the paths of 'number_angles' rays with the given scheme,
+    /// each sampled every 'angle_step' radians of orbit angle. Only the
+    /// obstacles that are themselves spherically symmetric (BlackHole,
+    /// BlackHolePredict, MaxDistance and BigBang) stop the integration, the
+    /// others are looked up when the rays are traced.
+    ///
+    /// Returns None if the metric is not static and spherically symmetric.
+    /// Panics with fewer than 2 angles, a non positive angle step, or an
+    /// observer on or inside the horizon, where it cannot stay at rest.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<M: Metric>(
+        metric: &M,
+        scene: &Scene,
+        observer_radius: f64,
+        number_angles: usize,
+        angle_step: f64,
+        number_steps: i32,
+        step_size: f64,
+        integrator: &dyn Integrator<M>,
+    ) -> Option<Self> {
+        check_sampling(number_angles, angle_step);
+        if !metric.spherically_symmetric() || !metric.cyclic_coordinates()[0] {
+            return None;
+        }
+        if let Some(horizon) = metric.horizon_radius() {
+            assert!(
+                observer_radius > horizon,
+                "Deflection table seen from radius {}, inside the horizon {}!",
+                observer_radius,
+                horizon
+            );
+        }
+        let (boundaries, disks) = partition(scene);
+        let paths = (0..number_angles)
+            .into_par_iter()
+            .map(|i| {
+                let emission_angle = (i as f64 + 0.5) * PI / number_angles as f64;
+                integrate_path(
+                    metric,
+                    &boundaries,
+                    observer_radius,
+                    emission_angle,
+                    angle_step,
+                    number_steps,
+                    step_size,
+                    integrator,
+                )
+            })
+            .collect();
+        Some(DeflectionTable {
+            observer_radius,
+            angle_step,
+            paths,
+            boundaries,
+            disks,
+        })
+    }
+
+    /// Same as `new` in Schwarzschild, with the closed form paths of
+    /// `PhotonOrbit` instead of integrated ones.
+    pub fn from_photon_orbits(
+        metric: &Schwarzschild,
+        scene: &Scene,
+        observer_radius: f64,
+        number_angles: usize,
+        angle_step: f64,
+    ) -> Self {
+        check_sampling(number_angles, angle_step);
+        let (boundaries, disks) = partition(scene);
+        let paths = (0..number_angles)
+            .into_par_iter()
+            .map(|i| {
+                let emission_angle = (i as f64 + 0.5) * PI / number_angles as f64;
+                sample_photon_orbit(
+                    metric,
+                    &boundaries,
+                    observer_radius,
+                    emission_angle,
+                    angle_step,
+                )
+            })
+            .collect();
+        DeflectionTable {
+            observer_radius,
+            angle_step,
+            paths,
+            boundaries,
+            disks,
+        }
+    }
+
+    /// Path of the given emission angle, interpolated between the two
+    /// closest paths of the table.
+    pub fn path(&self, emission_angle: f64) -> InterpolatedPath {
+        let number_angles = self.paths.len();
+        let index = (emission_angle * number_angles as f64 / PI - 0.5)
+            .clamp(0., (number_angles - 1) as f64);
+        let first = (index.floor() as usize).min(number_angles.saturating_sub(2));
+        let second = (first + 1).min(number_angles - 1);
+        InterpolatedPath {
+            before: &self.paths[first],
+            after: &self.paths[second],
+            weight: (index - first as f64).clamp(0., 1.),
+        }
+    }
+
+    /// Traces the ray leaving the observer placed at 'position'
+    /// (t, r, theta, phi) in the direction 'orientation', given as in
+    /// `Ray::new_i`, through the obstacles of the scene, from its path in the
+    /// table. Equivalent to integrating the ray, for a camera at the radius
+    /// the table was built for. The obstacles are those of the scene the
+    /// table was built from, the scene giving their colors. The disks lying
+    /// in the equator plane are only looked for where the path crosses it, on
+    /// the segment of an angle step around each crossing. The unresolved
+    /// paths exhaust their budget, see `Fate`. 'energy' is the energy of the
+    /// ray for the observer at rest, that of the camera being 1, by which the
+    /// redshift of the light is divided (see `change_frame`).
+    pub fn trace<M: Metric>(
+        &self,
+        metric: &M,
+        position: &Vector4,
+        orientation: &[f64; 2],
+        scene: &Scene,
+        energy: f64,
+    ) -> TraceOutcome {
+        let plane = OrbitPlane::new(position, orientation);
+        let path = self.path(plane.emission_angle);
+        let end_angle = path.nearest().end[2];
+        let to_scene = |orbit_angle: f64| {
+            let [t, r] = path.state(orbit_angle, self.angle_step);
+            let (theta, phi) = plane.direction(orbit_angle);
+            Vector4([position[0] + t, r, theta, phi])
+        };
+        for crossing in plane.equator_crossings(end_angle) {
+            let before = (crossing - self.angle_step / 2.).max(0.);
+            let after = (crossing + self.angle_step / 2.).min(end_angle);
+            let (old_scene_position, new_scene_position) = (to_scene(before), to_scene(after));
+            // Length of the chord of the segment
+            let length = (old_scene_position[1].powi(2) + new_scene_position[1].powi(2)
+                - 2. * old_scene_position[1] * new_scene_position[1] * (after - before).cos())
+            .max(0.)
+            .sqrt();
+            for obs in &self.disks {
+                let interpolation = obs.collision(&old_scene_position, &new_scene_position, length);
+                if interpolation >= 0. {
+                    let collision_position =
+                        to_scene(before * (1. - interpolation) + after * interpolation);
+                    let direction = escape_direction(&old_scene_position, &new_scene_position);
+                    let redshift = if scene.shifts(obs) {
+                        let ray = Ray::new_i(1., position, orientation, 1., metric);
+                        let outwards = new_scene_position[1] > old_scene_position[1];
+                        redshift(metric, &ray, &collision_position, outwards) / energy
+                    } else {
+                        1.
+                    };
+                    let collision = CollisionPoint {
+                        collision_point: collision_position,
+                        color: scene.color(obs, &collision_position, &direction, redshift),
+                    };
+                    return TraceOutcome::new(obs, collision, direction);
+                }
+            }
+        }
+        let fate = path.fate();
+        let boundary = self.boundaries.iter().find(|obs| match fate {
+            Fate::Captured => matches!(
+                obs,
+                Obstacle::BlackHole { r: _ } | Obstacle::BlackHolePredict { r: _ }
+            ),
+            Fate::Escaped { deflection: _ } => {
+                matches!(
+                    obs,
+                    Obstacle::MaxDistance { r: _ } | Obstacle::BigBang { t: _ }
+                )
+            }
+            Fate::Unresolved => false,
+        });
+        match boundary {
+            Some(obs) => {
+                let collision_position = to_scene(end_angle);
+                // The final direction of escaped rays is known exactly
+                let direction = match fate {
+                    Fate::Escaped { deflection } => {
+                        let (theta, phi) = plane.direction(plane.emission_angle + deflection);
+                        [theta, phi]
+                    }
+                    _ => escape_direction(
+                        &to_scene((end_angle - self.angle_step).max(0.)),
+                        &collision_position,
+                    ),
+                };
+                let collision = CollisionPoint {
+                    collision_point: collision_position,
+                    color: scene.color(obs, &collision_position, &direction, 1.),
+                };
+                TraceOutcome::new(obs, collision, direction)
+            }
+            None => TraceOutcome::BudgetExhausted,
+        }
+    }
+}
+
+/// Path of a ray between two paths of a `DeflectionTable`, with the given
+/// weight of the second one, interpolated where it is looked up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterpolatedPath<'a> {
+    pub before: &'a DeflectionPath,
+    pub after: &'a DeflectionPath,
+    pub weight: f64,
+}
+
+impl<'a> InterpolatedPath<'a> {
+    /// Closest of the two paths, which is followed where they end
+    /// differently, as on the edge of the shadow.
+    pub fn nearest(&self) -> &'a DeflectionPath {
+        if self.weight < 0.5 {
+            self.before
+        } else {
+            self.after
+        }
+    }
+
+    pub fn impact_parameter(&self) -> f64 {
+        self.before.impact_parameter * (1. - self.weight)
+            + self.after.impact_parameter * self.weight
+    }
+
+    /// Fate of the nearest path, with the interpolated deflection of the
+    /// escaping ones.
+    pub fn fate(&self) -> Fate {
+        match (self.before.fate, self.after.fate) {
+            (Fate::Escaped { deflection: a }, Fate::Escaped { deflection: b }) => Fate::Escaped {
+                deflection: a * (1. - self.weight) + b * self.weight,
+            },
+            _ => self.nearest().fate,
+        }
+    }
+
+    // (t, r) at the given orbit angle, interpolated between the crossings
+    // of both paths, then between the last crossing and the end of the
+    // nearest one
+    fn state(&self, orbit_angle: f64, angle_step: f64) -> [f64; 2] {
+        let nearest = self.nearest();
+        let crossing = |k: usize| {
+            let crossing = nearest.crossings.get(k)?;
+            Some(
+                match (self.before.crossings.get(k), self.after.crossings.get(k)) {
+                    (Some(a), Some(b)) => {
+                        [0, 1].map(|i| a[i] * (1. - self.weight) + b[i] * self.weight)
+                    }
+                    _ => *crossing,
+                },
+            )
+        };
+        let index = (orbit_angle / angle_step).max(0.);
+        let k = index.floor() as usize;
+        let (t_end, r_end, end_angle) = (nearest.end[0], nearest.end[1], nearest.end[2]);
+        match (crossing(k), crossing(k + 1)) {
+            (Some(a), Some(b)) => {
+                let weight = index - k as f64;
+                [0, 1].map(|i| a[i] * (1. - weight) + b[i] * weight)
+            }
+            (Some(a), None) => {
+                let span = end_angle - k as f64 * angle_step;
+                let weight = if span > 0. {
+                    ((orbit_angle - k as f64 * angle_step) / span).clamp(0., 1.)
+                } else {
+                    1.
+                };
+                [
+                    a[0] * (1. - weight) + t_end * weight,
+                    a[1] * (1. - weight) + r_end * weight,
+                ]
+            }
+            _ => [t_end, r_end],
+        }
+    }
+}
+
+/// Plane of the orbit of the ray leaving 'position' (t, r, theta, phi) in
+/// the direction 'orientation' (see `Ray::new_i`), in a spherically
+/// symmetric spacetime. The orbit angle of a point of the plane is its angle
+/// to the observer, growing along the ray.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrbitPlane {
+    pub e_r: [f64; 3],       // unit vector towards the observer
+    pub tangent: [f64; 3],   // unit vector at an orbit angle of PI / 2
+    pub emission_angle: f64, // between the ray and the outward radial direction
+}
+
+impl OrbitPlane {
+    pub fn new(position: &Vector4, orientation: &[f64; 2]) -> Self {
+        // Components (e_theta, e_phi, e_r) of the direction in the local frame
+        let direction = unit_vector(orientation[0], orientation[1]);
+        let (theta, phi) = (position[2], position[3]);
+        let e_theta = [
+            theta.cos() * phi.cos(),
+            theta.cos() * phi.sin(),
+            -theta.sin(),
+        ];
+        let e_phi = [-phi.sin(), phi.cos(), 0.];
+        // Any plane containing a radial ray will do
+        let mut tangent = [0, 1, 2].map(|i| e_theta[i] * direction[0] + e_phi[i] * direction[1]);
+        if dot(&tangent, &tangent) < 1e-24 {
+            tangent = e_theta;
+        }
+        OrbitPlane {
+            e_r: unit_vector(theta, phi),
+            tangent: normalized(&tangent),
+            emission_angle: direction[2].clamp(-1., 1.).acos(),
+        }
+    }
+
+    /// Orbit angles, below 'end_angle' and in increasing order, where the
+    /// plane crosses the equator of the scene theta = PI / 2, where
+    /// `Obstacle::Ring` and `Obstacle::AccretionDisk` lie. Planes lying in
+    /// the equator never cross it.
+    pub fn equator_crossings(&self, end_angle: f64) -> impl Iterator<Item = f64> {
+        let (normal, tangent) = (self.e_r[2], self.tangent[2]);
+        // The height cos(angle) e_r + sin(angle) tangent vanishes every PI
+        let first = if normal.abs() < 1e-12 && tangent.abs() < 1e-12 {
+            f64::INFINITY
+        } else {
+            (-normal).atan2(tangent).rem_euclid(PI)
+        };
+        (0..)
+            .map(move |k| first + k as f64 * PI)
+            .take_while(move |orbit_angle| *orbit_angle < end_angle)
+    }
+
+    /// Angles (theta, phi) of the scene of the point at the given orbit angle.
+    pub fn direction(&self, orbit_angle: f64) -> (f64, f64) {
+        let (sin, cos) = orbit_angle.sin_cos();
+        let direction = [0, 1, 2].map(|i| self.e_r[i] * cos + self.tangent[i] * sin);
+        (
+            direction[2].clamp(-1., 1.).acos(),
+            direction[1].atan2(direction[0]),
+        )
+    }
+}
+
+// Records the states (t, r) of a ray of the table moving in the equator
+// plane every angle_step of orbit angle, which photons sweep monotonically,
+// from its states (t, r, lambda, orbit angle), and finds the boundary of the
+// scene it meets.
+struct PathSampler<'a> {
+    boundaries: &'a [Obstacle],
+    angle_step: f64,
+    crossings: Vec<[f64; 2]>,
+    old: [f64; 4],
+}
+
+impl<'a> PathSampler<'a> {
+    fn new(boundaries: &'a [Obstacle], observer_radius: f64, angle_step: f64) -> Self {
+        PathSampler {
+            boundaries,
+            angle_step,
+            crossings: vec![[0., observer_radius]],
+            old: [0., observer_radius, 0., 0.],
+        }
+    }
+
+    fn push(&mut self, new: [f64; 4]) -> Option<&'a Obstacle> {
+        let old = self.old;
+        let old_scene_position = Vector4([old[0], old[1], PI / 2., old[3]]);
+        let new_scene_position = Vector4([new[0], new[1], PI / 2., new[3]]);
+        let boundary = self.boundaries.iter().find_map(|obs| {
+            let a = obs.collision(&old_scene_position, &new_scene_position, new[2] - old[2]);
+            if a >= 0. {
+                Some((obs, a))
+            } else {
+                None
+            }
+        });
+        // The path ends where it meets the boundary
+        let new = match boundary {
+            Some((_, a)) => [0, 1, 2, 3].map(|i| old[i] * (1. - a) + new[i] * a),
+            None => new,
+        };
+        while self.crossings.len() as f64 * self.angle_step <= new[3] {
+            let a = (self.crossings.len() as f64 * self.angle_step - old[3]) / (new[3] - old[3]);
+            self.crossings
+                .push([0, 1].map(|i| old[i] * (1. - a) + new[i] * a));
+        }
+        self.old = new;
+        boundary.map(|(obs, _)| obs)
+    }
+
+    fn path(self, emission_angle: f64, impact_parameter: f64, fate: Fate) -> DeflectionPath {
+        DeflectionPath {
+            emission_angle,
+            impact_parameter,
+            crossings: self.crossings,
+            end: [self.old[0], self.old[1], self.old[3]],
+            fate,
+        }
+    }
+}
+
+// Integrates the ray leaving the equator of the observer with the given
+// emission angle, towards increasing phi, so that it stays in the equator
+// plane and its orbit angle is phi, until it meets a boundary of the scene.
+#[allow(clippy::too_many_arguments)]
+fn integrate_path<M: Metric>(
+    metric: &M,
+    boundaries: &[Obstacle],
+    observer_radius: f64,
+    emission_angle: f64,
+    angle_step: f64,
+    number_steps: i32,
+    step_size: f64,
+    integrator: &dyn Integrator<M>,
+) -> DeflectionPath {
+    let mut ray = Ray::new_i(
+        step_size,
+        &Vector4([0., observer_radius, PI / 2., 0.]),
+        &[emission_angle, PI / 2.],
+        1.,
+        metric,
+    );
+    let constants = ray.constants_of_motion(metric);
+    let mut sampler = PathSampler::new(boundaries, observer_radius, angle_step);
+    let mut lambda = 0.;
+    let mut fate = Fate::Unresolved;
+    for _ in 0..number_steps {
+        lambda += integrator.step(&mut ray, step_size, metric);
+        if f64::is_nan(ray.position[1]) {
+            break;
+        }
+        if let Some(boundary) =
+            sampler.push([ray.position[0], ray.position[1], lambda, ray.position[3]])
+        {
+            fate = match boundary {
+                Obstacle::MaxDistance { r: _ } | Obstacle::BigBang { t: _ } => Fate::Escaped {
+                    deflection: deflection(&ray, metric, emission_angle),
+                },
+                _ => Fate::Captured,
+            };
+            break;
+        }
+    }
+    // The ray runs backwards in time, with a negative energy
+    let impact_parameter = (constants.angular_momentum / constants.energy).abs();
+    sampler.path(emission_angle, impact_parameter, fate)
+}
+
+// Samples the closed form orbit of the ray leaving the equator of the
+// observer with the given emission angle, until it meets a boundary of the
+// scene. The time coordinate and the affine parameter, from
+// dt / dphi = -c r² / (b (1 - rs / r)) and dlambda / dphi = r² / (b sqrt(1 - rs / r0))
+// for the normalization of `Ray::new_i`, are integrated with the midpoint
+// rule, which never evaluates them on the horizon.
+fn sample_photon_orbit(
+    metric: &Schwarzschild,
+    boundaries: &[Obstacle],
+    observer_radius: f64,
+    emission_angle: f64,
+    angle_step: f64,
+) -> DeflectionPath {
+    let orbit = PhotonOrbit::new(metric, observer_radius, emission_angle);
+    let b = orbit.impact_parameter;
+    let mut sampler = PathSampler::new(boundaries, observer_radius, angle_step);
+    let mut fate = if orbit.captured {
+        Fate::Captured
+    } else {
+        Fate::Escaped {
+            deflection: orbit.end_angle - emission_angle,
+        }
+    };
+    // Rays winding several times around the photon sphere are left
+    // unresolved, like rays running out of integration steps
+    let last_angle = orbit.end_angle.min(8. * PI);
+    if last_angle < orbit.end_angle {
+        fate = Fate::Unresolved;
+    }
+    let substep = angle_step / 8.;
+    let (mut t, mut lambda) = (0., 0.);
+    for k in 1.. {
+        let orbit_angle = (k as f64 * substep).min(last_angle);
+        // Escaping rays reach infinity at the end angle
+        if orbit_angle == orbit.end_angle && !orbit.captured {
+            break;
+        }
+        let middle = orbit.radius(orbit_angle - substep / 2.);
+        let d_angle = orbit_angle - (k - 1) as f64 * substep;
+        t -= metric.c * middle * middle / (b * (1. - metric.rs / middle)) * d_angle;
+        lambda += middle * middle / (b * (1. - metric.rs / observer_radius).sqrt()) * d_angle;
+        let r = orbit.radius(orbit_angle);
+        if let Some(boundary) = sampler.push([t, r, lambda, orbit_angle]) {
+            fate = match boundary {
+                Obstacle::MaxDistance { r: _ } | Obstacle::BigBang { t: _ } => {
+                    // Angle of the final direction to the radial one
+                    let final_angle = (b * (1. - metric.rs / r).sqrt() / r).clamp(-1., 1.).asin();
+                    Fate::Escaped {
+                        deflection: orbit_angle + final_angle - emission_angle,
+                    }
+                }
+                _ => Fate::Captured,
+            };
+            break;
+        }
+        if orbit_angle >= last_angle {
+            break;
+        }
+    }
+    sampler.path(emission_angle, metric.c * b, fate)
+}
+
+// Tables need at least two paths to interpolate, and a positive angle step
+fn check_sampling(number_angles: usize, angle_step: f64) {
+    assert!(
+        number_angles >= 2,
+        "Deflection table of {} angles, at least 2 are needed!",
+        number_angles
+    );
+    assert!(
+        angle_step > 0.,
+        "Deflection table sampled every {} radians!",
+        angle_step
+    );
+}
+
+// Spherically symmetric obstacles of the scene, which end the paths, and
+// the others
+fn partition(scene: &Scene) -> (Vec<Obstacle>, Vec<Obstacle>) {
+    scene.obstacles.iter().cloned().partition(|obs| {
+        matches!(
+            obs,
+            Obstacle::BlackHole { r: _ }
+                | Obstacle::BlackHolePredict { r: _ }
+                | Obstacle::MaxDistance { r: _ }
+                | Obstacle::BigBang { t: _ }
+        )
+    })
+}
+
+// Redshift factor of `KEPLERIAN` emitters at the scene position 'position'
+// for the ray of the table 'ray', given at the observer, moving outwards or
+// inwards there, see `Ray::redshift`. The energy and the angular momentum
+// vector of the ray are conserved, and its radial momentum follows from the
+// null condition of the diagonal metric.
+fn redshift<M: Metric>(metric: &M, ray: &Ray, position: &Vector4, outwards: bool) -> f64 {
+    let start = ray.scene_position(metric);
+    let initial = ray.scene_momentum(metric);
+    let angular_momentum = angular_momentum(initial[2], initial[3], start[2], start[3]);
+    let [p_theta, p_phi] = angular_components(&angular_momentum, position[2], position[3]);
+    let g_inv = metric.inverse(&metric.from_spherical(position));
+    let p_r = (-(g_inv[[0, 0]] * initial[0].powi(2)
+        + g_inv[[2, 2]] * p_theta.powi(2)
+        + g_inv[[3, 3]] * p_phi.powi(2))
+        / g_inv[[1, 1]])
+    .max(0.)
+    .sqrt();
+    let momentum = Vector4([
+        initial[0],
+        if outwards { p_r } else { -p_r },
+        p_theta,
+        p_phi,
+    ]);
+    1. / momentum.dot(&KEPLERIAN.velocity(metric, position))
+}
+
+// Angle between the final and initial directions of a ray moving in the
+// equator plane, from the angle of its final direction to the radial one
+// measured by the observer at rest.
+fn deflection<M: Metric>(ray: &Ray, metric: &M, emission_angle: f64) -> f64 {
+    let g = metric.components(&ray.position);
+    let final_angle = (g[[3, 3]].sqrt() * ray.position_derivative[3])
+        .atan2(g[[1, 1]].sqrt() * ray.position_derivative[1]);
+    ray.position[3] + final_angle - emission_angle
+}
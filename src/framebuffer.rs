@@ -0,0 +1,111 @@
+use crate::tonemap::ToneMapping;
+use image::{codecs::hdr::HdrEncoder, ImageBuffer, ImageFormat, ImageResult, Rgb, RgbImage};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Image rendered by a `Camera`. 'hdr' holds the linear radiance summed over
+/// the sub-rays of each pixel, row by row from the top left corner, and
+/// 'image' its 8-bit version, see `ToneMapping`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub hdr: Vec<Rgb<f64>>,
+    pub image: RgbImage,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32, hdr: Vec<Rgb<f64>>, tone_mapping: &ToneMapping) -> Self {
+        assert_eq!(
+            hdr.len(),
+            (width * height) as usize,
+            "Framebuffer of {} pixels for an image of {}x{}!",
+            hdr.len(),
+            width,
+            height
+        );
+        let mut framebuffer = Framebuffer {
+            width,
+            height,
+            hdr,
+            image: ImageBuffer::new(width, height),
+        };
+        framebuffer.tone_map(tone_mapping);
+        framebuffer
+    }
+
+    /// Computes the 8-bit image again from the radiance, with another tone
+    /// mapping.
+    pub fn tone_map(&mut self, tone_mapping: &ToneMapping) {
+        let colors = tone_mapping.apply(&self.hdr);
+        for (pixel, color) in self.image.pixels_mut().zip(colors) {
+            *pixel = color;
+        }
+    }
+
+    /// Linear radiance of the pixel (x, y).
+    pub fn hdr_pixel(&self, x: u32, y: u32) -> Rgb<f64> {
+        self.hdr[(y * self.width + x) as usize]
+    }
+
+    /// Saves the image at 'path', in the format given by its extension: the
+    /// linear radiance for the floating point Portable Float Map (.pfm) and
+    /// Radiance (.hdr) formats, the 8-bit image for the others image can
+    /// encode. Other extensions, such as OpenEXR's, are unsupported errors.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let path = path.as_ref();
+        let pfm = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map_or(false, |extension| extension.eq_ignore_ascii_case("pfm"));
+        if pfm {
+            return self.save_pfm(path);
+        }
+        self.save_with_format(path, ImageFormat::from_path(path)?)
+    }
+
+    /// Same as `save`, in the given format.
+    pub fn save_with_format<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: ImageFormat,
+    ) -> ImageResult<()> {
+        match format {
+            ImageFormat::Hdr => self.save_radiance(path),
+            _ => self.image.save_with_format(path, format),
+        }
+    }
+
+    /// Saves the linear radiance as a little-endian Portable Float Map, whose
+    /// rows go from the bottom to the top of the image.
+    pub fn save_pfm<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.hdr.chunks(self.width as usize).rev() {
+            for pixel in row {
+                for channel in pixel.0.iter() {
+                    file.write_all(&(*channel as f32).to_le_bytes())?;
+                }
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Saves the linear radiance as a Radiance RGBE image, with a shared
+    /// exponent and an 8-bit mantissa per channel.
+    pub fn save_radiance<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let pixels: Vec<Rgb<f32>> = self
+            .hdr
+            .iter()
+            .map(|pixel| Rgb(pixel.0.map(|channel| channel as f32)))
+            .collect();
+        let mut file = BufWriter::new(File::create(path)?);
+        HdrEncoder::new(&mut file).encode(&pixels, self.width as usize, self.height as usize)?;
+        file.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+use std::fmt::Debug;
+
+use crate::{
+    ray::Ray,
+    space::{CoordinateSystem, Metric},
+    tensor::{Christoffel, Matrix4, Vector4},
+};
+
+/// Numerical scheme advancing a ray along its geodesic, called by
+/// `Ray::trace_with`. Currently, the implemented schemes are:
+/// - Rk4: Classic fourth order Runge-Kutta with a fixed step, shrunk near
+///   horizons and poles by a heuristic when 'adaptive_step' is set.
+/// - DormandPrince: Embedded Runge-Kutta 5(4) pair. The local error of each
+///   step is estimated from the difference between the two orders, and the
+///   step is rejected and retried with a smaller size whenever it exceeds
+///   'absolute_tolerance' + 'relative_tolerance' * |state|. The step size
+///   of 'trace_with' is then only the initial and largest step.
+/// - StormerVerlet: Generalized Stormer-Verlet scheme on the Hamiltonian
+///   H = g^{mu nu} p_mu p_nu / 2. It is only second order, but symplectic,
+///   so the error on H = 0 (the null condition) stays bounded over long
+///   orbits instead of drifting. Its implicit stages are solved by fixed
+///   point iterations. The step heuristic of 'Rk4' can be enabled as well,
+///   at the cost of the symplectic property near the black hole.
+/// - Hamiltonian: RK4 on the position and the covariant momentum p_mu, with
+///   dx^mu/dlambda = dH/dp_mu and dp_mu/dlambda = -dH/dx^mu. The momenta of
+///   the metric's cyclic coordinates are left untouched, so the energy and
+///   angular momentum of a ray are conserved up to rounding errors. With
+///   'projection', the other components are rescaled after each step to
+///   bring H back to its initial value if the drift of the ray is tracked
+///   (see `Ray::track_drift`), to zero (the null condition) otherwise.
+pub trait Integrator<M: Metric>: Debug + Send + Sync {
+    /// Name of the scheme, reported in the ray's statistics.
+    fn name(&self) -> &'static str;
+    /// Advances the ray by one accepted step, no longer than `step_size`,
+    /// and returns the affine length of that step.
+    fn step(&self, ray: &mut Ray, step_size: f64, metric: &M) -> f64;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rk4 {
+    pub adaptive_step: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DormandPrince {
+    pub absolute_tolerance: f64,
+    pub relative_tolerance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StormerVerlet {
+    pub adaptive_step: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hamiltonian {
+    pub adaptive_step: bool,
+    pub projection: bool,
+}
+
+// Dormand-Prince 5(4) Butcher tableau, without the nodes since the geodesic
+// equation does not depend on the affine parameter. The fifth order weights
+// are the last row of the stage coefficients (first same as last).
+const DORMAND_PRINCE_COEFFICIENTS: [[f64; 6]; 7] = [
+    [0., 0., 0., 0., 0., 0.],
+    [1. / 5., 0., 0., 0., 0., 0.],
+    [3. / 40., 9. / 40., 0., 0., 0., 0.],
+    [44. / 45., -56. / 15., 32. / 9., 0., 0., 0.],
+    [
+        19372. / 6561.,
+        -25360. / 2187.,
+        64448. / 6561.,
+        -212. / 729.,
+        0.,
+        0.,
+    ],
+    [
+        9017. / 3168.,
+        -355. / 33.,
+        46732. / 5247.,
+        49. / 176.,
+        -5103. / 18656.,
+        0.,
+    ],
+    [
+        35. / 384.,
+        0.,
+        500. / 1113.,
+        125. / 192.,
+        -2187. / 6784.,
+        11. / 84.,
+    ],
+];
+const DORMAND_PRINCE_FOURTH_ORDER_WEIGHTS: [f64; 7] = [
+    5179. / 57600.,
+    0.,
+    7571. / 16695.,
+    393. / 640.,
+    -92097. / 339200.,
+    187. / 2100.,
+    1. / 40.,
+];
+
+// Fixed point iterations of the implicit Stormer-Verlet stages
+const STORMER_VERLET_ITERATIONS: usize = 10;
+const STORMER_VERLET_TOLERANCE: f64 = 1e-14;
+
+impl<M: Metric> Integrator<M> for Rk4 {
+    fn name(&self) -> &'static str {
+        "rk4"
+    }
+
+    fn step(&self, ray: &mut Ray, step_size: f64, metric: &M) -> f64 {
+        // Runge kutta 4 integration method, computes one step
+        let d_lambda = if self.adaptive_step {
+            heuristic_step_size(ray, step_size, metric)
+        } else {
+            step_size
+        };
+        let initial_position = ray.position;
+        let initial_position_derivative = ray.position_derivative;
+
+        let k1 = second_derivative(&initial_position, &initial_position_derivative, metric);
+        let k2 = second_derivative(
+            &(initial_position + (initial_position_derivative * d_lambda / 2.)),
+            &(initial_position_derivative + (k1 * d_lambda / 2.)),
+            metric,
+        );
+        let k3 = second_derivative(
+            &(initial_position
+                + (initial_position_derivative * d_lambda / 2.)
+                + (k1 * (d_lambda.powf(2.)) / 4.)),
+            &(initial_position_derivative + (k2 * d_lambda / 2.)),
+            metric,
+        );
+        let k4 = second_derivative(
+            &(initial_position
+                + (initial_position_derivative * d_lambda)
+                + (k2 * (d_lambda.powf(2.)) / 2.)),
+            &(initial_position_derivative + (k3 * d_lambda)),
+            metric,
+        );
+        ray.position = initial_position
+            + (d_lambda * initial_position_derivative)
+            + ((d_lambda.powf(2.)) / 6. * (k1 + k2 + k3));
+        ray.position_derivative =
+            initial_position_derivative + (d_lambda / 6. * (k1 + (2. * k2) + (2. * k3) + k4));
+        ray.statistics.accepted_steps += 1;
+        ray.statistics.evaluations += 4;
+        d_lambda
+    }
+}
+
+impl<M: Metric> Integrator<M> for DormandPrince {
+    fn name(&self) -> &'static str {
+        "rk45"
+    }
+
+    fn step(&self, ray: &mut Ray, step_size: f64, metric: &M) -> f64 {
+        let mut d_lambda = ray.proposed_step.unwrap_or(step_size).min(step_size);
+        if let Some(limit) = metric.step_limit(&ray.position, &ray.position_derivative) {
+            d_lambda = d_lambda.min(limit);
+        }
+        loop {
+            let (position, position_derivative, error) = self.attempt(ray, d_lambda, metric);
+            ray.statistics.evaluations += 7;
+            // Usual step size controller, a NaN error shrinks the step
+            let factor = (0.9 * error.powf(-0.2)).max(0.2).min(5.);
+            if error <= 1. || d_lambda < step_size * 1e-12 {
+                ray.position = position;
+                ray.position_derivative = position_derivative;
+                ray.proposed_step = Some(d_lambda * factor);
+                ray.statistics.accepted_steps += 1;
+                return d_lambda;
+            }
+            ray.statistics.rejected_steps += 1;
+            d_lambda *= factor;
+        }
+    }
+}
+
+impl DormandPrince {
+    // Dormand-Prince 5(4) step, returning the fifth order position and
+    // velocity with the norm of the estimated local error relative to the
+    // tolerances (the step is acceptable below 1)
+    fn attempt<M: Metric>(&self, ray: &Ray, d_lambda: f64, metric: &M) -> (Vector4, Vector4, f64) {
+        // Stages of the first order system (position, velocity)' = (velocity, acceleration)
+        let mut velocity_stages = [Vector4::zeros(); 7];
+        let mut acceleration_stages = [Vector4::zeros(); 7];
+        for (stage, coefficients) in DORMAND_PRINCE_COEFFICIENTS.iter().enumerate() {
+            let mut position = ray.position;
+            let mut velocity = ray.position_derivative;
+            for (previous, coefficient) in coefficients.iter().enumerate().take(stage) {
+                position += velocity_stages[previous] * (d_lambda * coefficient);
+                velocity += acceleration_stages[previous] * (d_lambda * coefficient);
+            }
+            acceleration_stages[stage] = second_derivative(&position, &velocity, metric);
+            velocity_stages[stage] = velocity;
+        }
+
+        let fifth_order = &DORMAND_PRINCE_COEFFICIENTS[6];
+        let mut position = ray.position;
+        let mut velocity = ray.position_derivative;
+        let mut position_error = Vector4::zeros();
+        let mut velocity_error = Vector4::zeros();
+        for stage in 0..7 {
+            let weight = if stage < 6 { fifth_order[stage] } else { 0. };
+            let error_weight = weight - DORMAND_PRINCE_FOURTH_ORDER_WEIGHTS[stage];
+            position += velocity_stages[stage] * (d_lambda * weight);
+            velocity += acceleration_stages[stage] * (d_lambda * weight);
+            position_error += velocity_stages[stage] * (d_lambda * error_weight);
+            velocity_error += acceleration_stages[stage] * (d_lambda * error_weight);
+        }
+
+        let mut error = 0.;
+        for i in 0..4 {
+            let position_scale = self.absolute_tolerance
+                + self.relative_tolerance * ray.position[i].abs().max(position[i].abs());
+            let velocity_scale = self.absolute_tolerance
+                + self.relative_tolerance * ray.position_derivative[i].abs().max(velocity[i].abs());
+            error += (position_error[i] / position_scale).powi(2)
+                + (velocity_error[i] / velocity_scale).powi(2);
+        }
+        (position, velocity, (error / 8.).sqrt())
+    }
+}
+
+impl<M: Metric> Integrator<M> for StormerVerlet {
+    fn name(&self) -> &'static str {
+        "stormer-verlet"
+    }
+
+    fn step(&self, ray: &mut Ray, step_size: f64, metric: &M) -> f64 {
+        let d_lambda = if self.adaptive_step {
+            heuristic_step_size(ray, step_size, metric)
+        } else {
+            step_size
+        };
+        let half_step = d_lambda / 2.;
+        let cyclic = metric.cyclic_coordinates();
+        let position = ray.position;
+        let momentum = metric.components(&position).dot(&ray.position_derivative);
+
+        // p_{n+1/2} = p_n - h/2 dH/dx(x_n, p_{n+1/2})
+        let g_inv = metric.inverse(&position);
+        let christoffel = metric.christoffel(&position);
+        ray.statistics.evaluations += 1;
+        let mut half_momentum = momentum;
+        for _ in 0..STORMER_VERLET_ITERATIONS {
+            let next = momentum
+                - hamiltonian_gradient(&g_inv, &christoffel, &half_momentum, &cyclic) * half_step;
+            let converged = is_converged(&next, &half_momentum);
+            half_momentum = next;
+            if converged {
+                break;
+            }
+        }
+
+        // x_{n+1} = x_n + h/2 (dH/dp(x_n, p_{n+1/2}) + dH/dp(x_{n+1}, p_{n+1/2}))
+        let initial_velocity = g_inv.dot(&half_momentum);
+        let mut new_position = position + initial_velocity * d_lambda;
+        let mut new_g_inv = metric.inverse(&new_position);
+        for _ in 0..STORMER_VERLET_ITERATIONS {
+            let next = position + (initial_velocity + new_g_inv.dot(&half_momentum)) * half_step;
+            let converged = is_converged(&next, &new_position);
+            new_position = next;
+            new_g_inv = metric.inverse(&new_position);
+            if converged {
+                break;
+            }
+        }
+
+        // p_{n+1} = p_{n+1/2} - h/2 dH/dx(x_{n+1}, p_{n+1/2})
+        let new_christoffel = metric.christoffel(&new_position);
+        ray.statistics.evaluations += 1;
+        let new_momentum = half_momentum
+            - hamiltonian_gradient(&new_g_inv, &new_christoffel, &half_momentum, &cyclic)
+                * half_step;
+
+        ray.position_derivative = new_g_inv.dot(&new_momentum);
+        ray.position = new_position;
+        ray.statistics.accepted_steps += 1;
+        d_lambda
+    }
+}
+
+impl<M: Metric> Integrator<M> for Hamiltonian {
+    fn name(&self) -> &'static str {
+        "hamiltonian"
+    }
+
+    fn step(&self, ray: &mut Ray, step_size: f64, metric: &M) -> f64 {
+        let d_lambda = if self.adaptive_step {
+            heuristic_step_size(ray, step_size, metric)
+        } else {
+            step_size
+        };
+        let cyclic = metric.cyclic_coordinates();
+        // Hamilton's equations, (dx/dlambda, dp/dlambda)
+        let derivatives = |position: &Vector4, momentum: &Vector4| {
+            let g_inv = metric.inverse(position);
+            let christoffel = metric.christoffel(position);
+            (
+                g_inv.dot(momentum),
+                -hamiltonian_gradient(&g_inv, &christoffel, momentum, &cyclic),
+            )
+        };
+        let position = ray.position;
+        let momentum = metric.components(&position).dot(&ray.position_derivative);
+
+        let (k1_x, k1_p) = derivatives(&position, &momentum);
+        let (k2_x, k2_p) = derivatives(
+            &(position + k1_x * (d_lambda / 2.)),
+            &(momentum + k1_p * (d_lambda / 2.)),
+        );
+        let (k3_x, k3_p) = derivatives(
+            &(position + k2_x * (d_lambda / 2.)),
+            &(momentum + k2_p * (d_lambda / 2.)),
+        );
+        let (k4_x, k4_p) =
+            derivatives(&(position + k3_x * d_lambda), &(momentum + k3_p * d_lambda));
+        let new_position = position + (k1_x + 2. * k2_x + 2. * k3_x + k4_x) * (d_lambda / 6.);
+        let mut new_momentum = momentum + (k1_p + 2. * k2_p + 2. * k3_p + k4_p) * (d_lambda / 6.);
+
+        let g_inv = metric.inverse(&new_position);
+        if self.projection {
+            let target = ray
+                .statistics
+                .initial_constants
+                .map_or(0., |constants| constants.hamiltonian);
+            project_on_mass_shell(&mut new_momentum, &g_inv, target, &cyclic);
+        }
+        ray.position_derivative = g_inv.dot(&new_momentum);
+        ray.position = new_position;
+        ray.statistics.accepted_steps += 1;
+        ray.statistics.evaluations += 4;
+        d_lambda
+    }
+}
+
+// Rescales the components of the momentum that are not constants of motion
+// (all but p_t if none is) so that g^{mu nu} p_mu p_nu / 2 = target, by the
+// factor closest to 1
+fn project_on_mass_shell(momentum: &mut Vector4, g_inv: &Matrix4, target: f64, cyclic: &[bool; 4]) {
+    let mut kept = *cyclic;
+    if !kept.iter().any(|&is_kept| is_kept) {
+        kept[0] = true;
+    }
+    let mut constant_part = Vector4::zeros();
+    let mut scaled_part = Vector4::zeros();
+    for mu in 0..4 {
+        if kept[mu] {
+            constant_part[mu] = momentum[mu];
+        } else {
+            scaled_part[mu] = momentum[mu];
+        }
+    }
+    // 2 H(s) = a s^2 + 2 b s + c + 2 target
+    let a = scaled_part.dot(&g_inv.dot(&scaled_part));
+    let b = constant_part.dot(&g_inv.dot(&scaled_part));
+    let c = constant_part.dot(&g_inv.dot(&constant_part)) - 2. * target;
+    let discriminant = b * b - a * c;
+    if a == 0. || discriminant < 0. {
+        return;
+    }
+    let root = discriminant.sqrt();
+    let (s_1, s_2) = ((-b + root) / a, (-b - root) / a);
+    let s = if (s_1 - 1.).abs() < (s_2 - 1.).abs() {
+        s_1
+    } else {
+        s_2
+    };
+    *momentum = constant_part + scaled_part * s;
+}
+
+// dH/dx^mu = 1/2 d_mu g^{alpha beta} p_alpha p_beta = -Gamma^alpha_{mu lambda} p^lambda p_alpha,
+// exactly zero for the cyclic coordinates
+fn hamiltonian_gradient(
+    g_inv: &Matrix4,
+    christoffel: &Christoffel,
+    momentum: &Vector4,
+    cyclic: &[bool; 4],
+) -> Vector4 {
+    let velocity = g_inv.dot(momentum);
+    let mut gradient = Vector4::zeros();
+    for mu in (0..4).filter(|&mu| !cyclic[mu]) {
+        for alpha in 0..4 {
+            for lambda in 0..4 {
+                gradient[mu] -=
+                    christoffel[[alpha, mu, lambda]] * velocity[lambda] * momentum[alpha];
+            }
+        }
+    }
+    gradient
+}
+
+fn is_converged(next: &Vector4, previous: &Vector4) -> bool {
+    let change = (*next - *previous).max_norm();
+    let scale = next.max_norm().max(1.);
+    change <= STORMER_VERLET_TOLERANCE * scale
+}
+
+// Step size of the adaptive integrations, shrunk where the coordinates are
+// badly behaved
+fn heuristic_step_size<M: Metric>(ray: &Ray, step_size: f64, metric: &M) -> f64 {
+    let mut d_lambda = step_size;
+    // Cartesian coordinates are regular at the horizon and on the axis
+    let cartesian = metric.coordinate_system() == CoordinateSystem::Cartesian;
+    if !cartesian {
+        if let Some(rs) = metric.horizon_radius() {
+            d_lambda = (step_size * (1. - rs / ray.position[1]).abs()).max(rs * step_size / 200.);
+        }
+    }
+    // Rays traced in their orbit plane stay away from the axis
+    if !cartesian && ray.rotation.is_none() {
+        let pole_orth_velocity = ((ray.position[1] * ray.position_derivative[2]).powi(2)
+            + (ray.position[1] * ray.position_derivative[3] * (ray.position[2]).sin()).powi(2))
+        .sqrt();
+
+        let pole_distance = (ray.position[1] * ray.position[2].sin()).abs();
+        if pole_distance < step_size * pole_orth_velocity * 1.5 {
+            //d_lambda = step_size.sqrt() * pole_distance / pole_orth_velocity / 10.;
+            d_lambda = step_size.min(pole_distance / 20.);
+        }
+    }
+    if let Some(limit) = metric.step_limit(&ray.position, &ray.position_derivative) {
+        d_lambda = d_lambda.min(limit);
+    }
+    d_lambda
+}
+
+fn second_derivative<M: Metric>(
+    // Computes second derivative of movement at given position and velocity, in given metric
+    position: &Vector4,
+    position_derivative: &Vector4,
+    metric: &M,
+) -> Vector4 {
+    let christoffel = metric.christoffel(position);
+    let mut second_derivative = Vector4::zeros();
+    for i in 0..4 {
+        for j in 0..4 {
+            for k in 0..4 {
+                second_derivative[i] -=
+                    christoffel[[i, j, k]] * position_derivative[j] * position_derivative[k];
+            }
+        }
+    }
+    second_derivative
+}
@@ -1,4 +1,14 @@
+pub mod autodiff;
+pub mod background;
 pub mod camera;
+pub mod deflection;
+pub mod framebuffer;
+pub mod integrator;
+pub mod observer;
 pub mod obstacle;
+pub mod photon_orbit;
 pub mod ray;
+pub mod scene;
 pub mod space;
+pub mod tensor;
+pub mod tonemap;
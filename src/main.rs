@@ -10,61 +10,74 @@ fn main() {
 mod unit_tests {
     use std::f64::consts::PI;
 
-    use image::{ImageBuffer, RgbImage};
-    use ndarray::{Array1, Array3};
+    use image::codecs::hdr::HdrDecoder;
+    use image::{ImageBuffer, Rgb, RgbImage};
+    use ndarray::Array1;
+    use relativistic_ray_tracing::autodiff::{christoffel_error, AutoDiffMetric};
+    use relativistic_ray_tracing::background::{
+        escape_direction, Background, SkyTexture, Starfield,
+    };
     use relativistic_ray_tracing::camera::Camera;
+    use relativistic_ray_tracing::deflection::{DeflectionTable, Fate, OrbitPlane};
+    use relativistic_ray_tracing::framebuffer::Framebuffer;
+    use relativistic_ray_tracing::integrator::{DormandPrince, Hamiltonian, Rk4, StormerVerlet};
+    use relativistic_ray_tracing::observer::{change_frame, Observer};
     use relativistic_ray_tracing::obstacle::Obstacle;
-    use relativistic_ray_tracing::{ray::Ray, space::Space};
+    use relativistic_ray_tracing::photon_orbit::{carlson_rf, PhotonOrbit};
+    use relativistic_ray_tracing::ray::{Ray, TraceOutcome};
+    use relativistic_ray_tracing::scene::{Redshift, Scene};
+    use relativistic_ray_tracing::space::{
+        CoordinateSystem, EllisWormhole, Flrw, Kerr, KerrNewman, KerrSchild, Metric, Minkowski,
+        ReissnerNordstrom, Schwarzschild, SchwarzschildDeSitter,
+    };
+    use relativistic_ray_tracing::tensor::Vector4;
+    use relativistic_ray_tracing::tonemap::{luminance, Exposure, Operator, ToneMapping};
 
     #[test]
     fn ray_tracing() {
-        let position = Array1::<f64>::ones(4).mapv(|elem| elem * 2.);
+        let position = Vector4([2.; 4]);
         println!("test {:?}", position);
 
-        let espace = Space {
-            rs: 1.0,
-            c: 1.0,
-            christoffel: Array3::zeros((4, 4, 4)),
-            obstacles: Vec::new(),
-        };
+        let metric = Schwarzschild { rs: 1.0, c: 1.0 };
+        let scene = Scene::default();
 
         let mut ray = Ray::new();
 
         println!("ray intialised {:?}", ray);
-        println!("BEFORE : Space {:?}", espace);
-        espace.update_christoffel(&position);
-        println!("AFTER : and space {:?}", espace);
-        println!("{:?}", espace.christoffel[[0, 0, 1]]);
+        println!("Metric {:?} and scene {:?}", metric, scene);
+        println!("{:?}", metric.christoffel(&position)[[0, 0, 1]]);
 
         println!("\nTest trace() --> ray init. at (t, r, theta, phi) = (0, 6, PI/2, 0),\nwith velocity = 0 (and thus proper time speed = 1).");
         println!("=> r should decrease as the object falls into the black hole.");
         ray.position[1] = 6.;
         ray.position[2] = PI / 2.;
         ray.position_derivative[0] = 1.;
-        ray.trace(&espace, 10, 1., true, false);
+        ray.trace(&metric, &scene, 10, 1., true, false);
     }
 
     #[test]
     fn circular_orbit() {
-        let space = Space {
-            rs: 1.0,
-            c: 1.0,
-            christoffel: Array3::zeros((4, 4, 4)),
-            obstacles: Vec::new(),
-        };
-        let mut position = Array1::<f64>::zeros(4);
+        let metric = Schwarzschild { rs: 1.0, c: 1.0 };
+        let scene = Scene::default();
+        let mut position = Vector4::zeros();
         position[1] = 1.5;
         position[2] = PI / 2.;
-        let mut orientation = Array1::<f64>::zeros(3);
+        let mut orientation = [0.; 2];
         orientation[0] = PI / 2.;
         orientation[1] = PI / 2.;
-        let initial_velocity = space.c; //(C.powf(2.) * space.rs / 2. / (position[1] - space.rs)).sqrt();
+        let initial_velocity = metric.c; //(C.powf(2.) * metric.rs / 2. / (position[1] - metric.rs)).sqrt();
 
         let step_size = 0.4;
         let number_steps = 50;
-        let mut ray = Ray::new_i(step_size, &position, &orientation, initial_velocity, &space);
+        let mut ray = Ray::new_i(
+            step_size,
+            &position,
+            &orientation,
+            initial_velocity,
+            &metric,
+        );
 
-        ray.trace(&space, number_steps, step_size, true, true);
+        ray.trace(&metric, &scene, number_steps, step_size, true, true);
 
         let error_margin = 1e-3;
         println!("Test initial position : {:?}", position);
@@ -77,30 +90,32 @@ mod unit_tests {
 
     #[test]
     fn outward_escape() {
-        let space = Space {
-            rs: 100.,
-            c: 1.0,
-            christoffel: Array3::zeros((4, 4, 4)),
-            obstacles: Vec::new(),
-        };
-        let mut position = Array1::<f64>::zeros(4);
+        let metric = Schwarzschild { rs: 100., c: 1.0 };
+        let scene = Scene::default();
+        let mut position = Vector4::zeros();
         position[1] = 200.;
         position[2] = PI / 2.;
-        let mut orientation = Array1::<f64>::zeros(3);
+        let mut orientation = [0.; 2];
         orientation[0] = 0.;
         orientation[1] = 0.;
-        let initial_velocity = space.c; // Escapes at light speed : it's a photon
+        let initial_velocity = metric.c; // Escapes at light speed : it's a photon
 
         let step_size = 2.;
         let number_steps = 100;
-        let mut ray = Ray::new_i(step_size, &position, &orientation, initial_velocity, &space);
+        let mut ray = Ray::new_i(
+            step_size,
+            &position,
+            &orientation,
+            initial_velocity,
+            &metric,
+        );
 
-        ray.trace(&space, number_steps, step_size, true, true);
+        ray.trace(&metric, &scene, number_steps, step_size, true, true);
 
         let momentum_conservation = -ray.position_derivative[0].powi(2)
-            * (1. - space.rs / ray.position[1])
-            / space.c.powi(2)
-            + ray.position_derivative[1].powi(2) / (1. - space.rs / ray.position[1])
+            * (1. - metric.rs / ray.position[1])
+            / metric.c.powi(2)
+            + ray.position_derivative[1].powi(2) / (1. - metric.rs / ray.position[1])
             + (ray.position_derivative[2] * ray.position[1]).powi(2)
             + (ray.position_derivative[3] * ray.position[1] * ray.position[2].sin()).powi(2);
 
@@ -115,6 +130,1239 @@ mod unit_tests {
         );
     }
 
+    #[test]
+    fn metric_inverse() {
+        let metric = Schwarzschild { rs: 1.0, c: 1.0 };
+        let mut position = Vector4::zeros();
+        position[1] = 4.;
+        position[2] = PI / 3.;
+        let product = metric
+            .components(&position)
+            .matrix_product(&metric.inverse(&position));
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1. } else { 0. };
+                assert!((product[[i, j]] - expected).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn dormand_prince_tolerance() {
+        let metric = Schwarzschild { rs: 1.0, c: 1.0 };
+        let scene = Scene {
+            obstacles: Vec::from([Obstacle::MaxDistance { r: 30. }]),
+            background: Background::default(),
+            redshift: Redshift::Off,
+        };
+        let mut position = Vector4::zeros();
+        position[1] = 20.;
+        position[2] = PI / 2.;
+        let mut orientation = [0.; 2];
+        orientation[0] = PI - 0.14; // Strongly deflected, close to the photon sphere
+        orientation[1] = PI / 2.;
+        let conserved = |ray: &Ray| {
+            let r = ray.position[1];
+            (
+                (1. - 1. / r) * ray.position_derivative[0],
+                r * r * ray.position_derivative[3],
+            )
+        };
+
+        let (energy, angular_momentum) =
+            conserved(&Ray::new_i(5., &position, &orientation, 1., &metric));
+        let mut drifts = Vec::new();
+        for tolerance in [1e-6, 1e-10] {
+            let integrator = DormandPrince {
+                absolute_tolerance: tolerance,
+                relative_tolerance: tolerance,
+            };
+            let mut ray = Ray::new_i(5., &position, &orientation, 1., &metric);
+            let result = ray.trace_with(&metric, &scene, 10000, 5., &integrator, false);
+            assert!(
+                matches!(result.outcome, TraceOutcome::Escaped { .. }),
+                "The ray should escape"
+            );
+            let (final_energy, final_angular_momentum) = conserved(&ray);
+            let drift = (final_energy / energy - 1.)
+                .abs()
+                .max((final_angular_momentum / angular_momentum - 1.).abs());
+            println!(
+                "Tolerance {} : final position {:?}, drift {}",
+                tolerance, ray.position, drift
+            );
+            assert!(drift < tolerance * 100.);
+            drifts.push(drift);
+        }
+        assert!(drifts[1] < drifts[0] * 1e-2);
+    }
+
+    #[test]
+    fn symplectic_long_orbit() {
+        let metric = Schwarzschild { rs: 1.0, c: 1.0 };
+        let scene = Scene::default();
+        // Slightly eccentric timelike orbit around r = 20, of period ~800
+        let r: f64 = 20.;
+        let mass = 0.5;
+        let mut initial = Ray::new();
+        initial.position[1] = r;
+        initial.position[2] = PI / 2.;
+        initial.position_derivative[0] = 1. / (1. - 3. * mass / r).sqrt();
+        initial.position_derivative[3] =
+            1.05 * (mass / r.powi(3)).sqrt() / (1. - 3. * mass / r).sqrt();
+        let norm = |ray: &Ray| {
+            let g = metric.components(&ray.position);
+            ray.position_derivative
+                .dot(&g.dot(&ray.position_derivative))
+        };
+        let initial_norm = norm(&initial);
+
+        // Largest drift over the first and the second half of the integration
+        let mut drifts = Vec::new();
+        for scheme in ["rk4", "stormer-verlet"] {
+            let mut ray = initial.clone();
+            let mut largest_drifts: [f64; 2] = [0., 0.];
+            for chunk in 0..400 {
+                if scheme == "rk4" {
+                    let integrator = Rk4 {
+                        adaptive_step: false,
+                    };
+                    ray.trace_with(&metric, &scene, 50, 40., &integrator, false);
+                } else {
+                    let integrator = StormerVerlet {
+                        adaptive_step: false,
+                    };
+                    ray.trace_with(&metric, &scene, 50, 40., &integrator, false);
+                }
+                let drift = (norm(&ray) - initial_norm).abs();
+                largest_drifts[chunk / 200] = largest_drifts[chunk / 200].max(drift);
+            }
+            println!(
+                "{} : {:?}, largest drifts {:?}",
+                scheme, ray.statistics, largest_drifts
+            );
+            assert_eq!(ray.statistics.scheme, scheme);
+            assert_eq!(ray.statistics.accepted_steps, 20000);
+            drifts.push(largest_drifts);
+        }
+        // RK4 drifts away from the mass shell, the symplectic error stays bounded
+        assert!(drifts[0][1] > 1.5 * drifts[0][0]);
+        assert!(drifts[1][1] < 1.01 * drifts[1][0]);
+    }
+
+    #[test]
+    fn hamiltonian_conservation() {
+        let metric = Schwarzschild { rs: 1.0, c: 1.0 };
+        let scene = Scene::default();
+        // Light ray whirling a few times close to the photon sphere
+        let mut position = Vector4::zeros();
+        position[1] = 10.;
+        position[2] = PI / 2.;
+        let mut orientation = [0.; 2];
+        orientation[0] = PI / 2.;
+        orientation[1] = PI - 0.2596;
+        let initial = Ray::new_i(0.1, &position, &orientation, 1., &metric);
+
+        let mut drifts = Vec::new();
+        for projection in [false, true] {
+            let mut ray = initial.clone();
+            ray.track_drift();
+            let integrator = Hamiltonian {
+                adaptive_step: false,
+                projection,
+            };
+            ray.trace_with(&metric, &scene, 2000, 0.05, &integrator, false);
+            println!("projection {} : {:?}", projection, ray.statistics);
+            let initial_constants = ray.statistics.initial_constants.unwrap();
+            let drift = ray.statistics.drift.unwrap();
+            assert_eq!(ray.statistics.scheme, "hamiltonian");
+            assert!(drift.energy < 1e-12 * initial_constants.energy.abs());
+            assert!(drift.angular_momentum < 1e-12 * initial_constants.angular_momentum.abs());
+            drifts.push(drift);
+        }
+        // RK4 on the velocities changes the conserved quantities, which are
+        // only tracked on demand
+        let mut ray = initial.clone();
+        let rk4 = Rk4 {
+            adaptive_step: false,
+        };
+        ray.trace_with(&metric, &scene, 2000, 0.05, &rk4, false);
+        assert_eq!(ray.statistics.drift, None);
+        assert_eq!(ray.statistics.evaluations, 4 * 2000);
+        let mut ray = initial;
+        ray.track_drift();
+        ray.trace_with(&metric, &scene, 2000, 0.05, &rk4, false);
+        println!("rk4 : {:?}", ray.statistics);
+        assert_eq!(ray.statistics.evaluations, 4 * 2000 + 2001);
+        let drift = ray.statistics.drift.unwrap();
+        assert!(drift.angular_momentum > 100. * drifts[0].angular_momentum);
+        // The projection keeps the ray on the light cone
+        assert!(drifts[1].hamiltonian < 1e-14);
+        assert!(drifts[1].hamiltonian < 1e-2 * drifts[0].hamiltonian);
+    }
+
+    #[test]
+    fn automatic_christoffel() {
+        let schwarzschild = Schwarzschild { rs: 1.0, c: 1.0 };
+        let kerr_newman = KerrNewman {
+            rs: 1.0,
+            a: 0.3,
+            q: 0.2,
+        };
+        let automatic_schwarzschild = AutoDiffMetric {
+            function: schwarzschild.clone(),
+            horizon_radius: Some(1.0),
+            coordinate_system: CoordinateSystem::Spherical,
+        };
+        let automatic_kerr_newman = AutoDiffMetric {
+            function: kerr_newman.clone(),
+            horizon_radius: kerr_newman.horizon_radius(),
+            coordinate_system: CoordinateSystem::Spherical,
+        };
+        for (r, theta) in [(1.5, 0.3), (4., PI / 3.), (20., PI / 2.), (7., 2.9)] {
+            let mut position = Vector4::zeros();
+            position[1] = r;
+            position[2] = theta;
+            assert!(christoffel_error(&schwarzschild, &automatic_schwarzschild, &position) < 1e-12);
+            assert!(christoffel_error(&kerr_newman, &automatic_kerr_newman, &position) < 1e-12);
+            let (inverse, automatic_inverse) = (
+                kerr_newman.inverse(&position),
+                automatic_kerr_newman.inverse(&position),
+            );
+            for i in 0..4 {
+                assert!((inverse.row(i) - automatic_inverse.row(i)).max_norm() < 1e-12);
+            }
+        }
+
+        // Both versions of the metric trace the same ray
+        let mut position = Vector4::zeros();
+        position[1] = 6.;
+        position[2] = PI / 2.;
+        let mut orientation = [0.; 2];
+        orientation[0] = PI / 2.;
+        orientation[1] = PI / 2.;
+        let scene = Scene::default();
+        let mut ray = Ray::new_i(0.1, &position, &orientation, 1., &schwarzschild);
+        let mut automatic_ray =
+            Ray::new_i(0.1, &position, &orientation, 1., &automatic_schwarzschild);
+        ray.trace(&schwarzschild, &scene, 100, 0.1, false, false);
+        automatic_ray.trace(&automatic_schwarzschild, &scene, 100, 0.1, false, false);
+        let difference = ray.position - automatic_ray.position;
+        assert!(difference.dot(&difference).sqrt() < 1e-9);
+    }
+
+    #[test]
+    fn kerr_schild_horizon_crossing() {
+        let metric = KerrSchild { rs: 1.0, a: 0.4 };
+        let outer_horizon = metric.horizon_radius().unwrap();
+        let inner_horizon = metric.inner_horizon_radius().unwrap();
+        let mut position = Vector4::zeros();
+        position[1] = 6.;
+        position[2] = PI / 3.;
+        position[3] = 1.;
+        let round_trip = metric.to_spherical(&metric.from_spherical(&position));
+        assert!((round_trip - position).max_norm() < 1e-12);
+
+        let scene = Scene::default();
+        let mut orientation = [0.; 2];
+        orientation[0] = PI;
+        let mut ray = Ray::new_i(0.05, &position, &orientation, 1., &metric);
+        let mut r = position[1];
+        let mut steps = 0;
+        while r > (outer_horizon + inner_horizon) / 2. && steps < 1000 {
+            ray.trace(&metric, &scene, 1, 0.05, true, false);
+            r = metric.to_spherical(&ray.position)[1];
+            steps += 1;
+        }
+        println!("Final position : {:?} after {} steps", ray.position, steps);
+        assert!(r < outer_horizon && steps < 1000);
+        let g = metric.components(&ray.position);
+        let null_condition = ray
+            .position_derivative
+            .dot(&g.dot(&ray.position_derivative));
+        assert!(null_condition.abs() < 1e-6);
+
+        // Rays flying over the pole need no special treatment either
+        position[2] = 0.1;
+        orientation[0] = PI / 2.;
+        orientation[1] = PI;
+        let mut ray = Ray::new_i(0.05, &position, &orientation, 1., &metric);
+        ray.trace(&metric, &scene, 200, 0.05, true, false);
+        let final_position = metric.to_spherical(&ray.position);
+        println!("Final position : {:?}", final_position);
+        assert!((final_position[3] - position[3]).abs() > PI / 2.);
+        assert!(final_position[1] > position[1]);
+        // and their state is given back in the scene's coordinates
+        let derivative = ray.scene_position_derivative(&metric);
+        let jacobian = metric.spherical_jacobian(&final_position);
+        assert!((jacobian.dot(&derivative) - ray.position_derivative).max_norm() < 1e-9);
+    }
+
+    #[test]
+    fn kerr_frame_dragging() {
+        let metric = Kerr { rs: 1.0, a: 0.45 };
+        let scene = Scene::default();
+        let mut position = Vector4::zeros();
+        position[1] = 6.;
+        position[2] = PI / 2.;
+        let mut orientation = [0.; 2];
+        orientation[0] = PI; // Radially inwards for the zero angular momentum observer
+
+        let mut ray = Ray::new_i(0.05, &position, &orientation, 1., &metric);
+        let g = metric.components(&ray.position);
+        let null_condition = ray
+            .position_derivative
+            .dot(&g.dot(&ray.position_derivative));
+        assert!(null_condition.abs() < 1e-12);
+
+        ray.trace(&metric, &scene, 60, 0.05, false, false);
+        let g = metric.components(&ray.position);
+        let null_condition = ray
+            .position_derivative
+            .dot(&g.dot(&ray.position_derivative));
+        println!("Final position : {:?}", ray.position);
+        // The photon falls in and is dragged along the rotation of the black hole
+        assert!(ray.position[1] < position[1]);
+        assert!(ray.position[3] / ray.position[0] > 1e-3);
+        assert!(null_condition.abs() < 1e-6);
+    }
+
+    #[test]
+    fn charged_photon_sphere() {
+        let metric = ReissnerNordstrom { rs: 1.0, q: 0.4 };
+        let scene = Scene::default();
+        let (outer, inner) = (0.8, 0.2);
+        assert!((metric.horizon_radius().unwrap() - outer).abs() < 1e-12);
+        assert!((metric.inner_horizon_radius().unwrap() - inner).abs() < 1e-12);
+        assert!(matches!(
+            Obstacle::black_hole(&metric),
+            Some(Obstacle::BlackHole { r }) if (r - outer).abs() < 1e-12
+        ));
+        // Beyond the extremal charge or spin, the singularity is naked
+        let naked = ReissnerNordstrom { rs: 1.0, q: 0.6 };
+        assert_eq!(naked.horizon_radius(), None);
+        assert_eq!(naked.inner_horizon_radius(), None);
+        assert_eq!(Obstacle::black_hole(&naked), None);
+        assert_eq!(Kerr { rs: 1.0, a: 0.6 }.horizon_radius(), None);
+        assert_eq!(KerrSchild { rs: 1.0, a: 0.6 }.horizon_radius(), None);
+
+        // Charge shrinks the photon sphere below the Schwarzschild value 1.5 rs
+        let mass = metric.rs / 2.;
+        let photon_sphere = (3. * mass + (9. * mass * mass - 8. * metric.q.powi(2)).sqrt()) / 2.;
+        let mut position = Vector4::zeros();
+        position[1] = photon_sphere;
+        position[2] = PI / 2.;
+        let mut orientation = [0.; 2];
+        orientation[0] = PI / 2.;
+        orientation[1] = PI / 2.;
+
+        let mut ray = Ray::new_i(0.1, &position, &orientation, 1., &metric);
+        ray.trace(&metric, &scene, 50, 0.1, false, false);
+        println!("Test final position : {:?}", ray.position);
+        assert!((ray.position[1] - photon_sphere).abs() < 1e-3);
+        assert!(ray.position[3] > 1.);
+    }
+
+    #[test]
+    fn wormhole_traversal() {
+        let far_side = Rgb::<f64>([0., 0., 255.]);
+        let metric = EllisWormhole { throat_radius: 1. };
+        let scene = Scene {
+            obstacles: Vec::from([Obstacle::MaxDistance { r: 20. }]),
+            background: Background::TwoSided {
+                positive: Box::new(Background::default()),
+                negative: Box::new(Background::Uniform(far_side)),
+            },
+            redshift: Redshift::Off,
+        };
+        let mut position = Vector4::zeros();
+        position[1] = 10.;
+        position[2] = PI / 2.;
+        let mut orientation = [0.; 2];
+        orientation[0] = PI;
+
+        let mut ray = Ray::new_i(0.1, &position, &orientation, 1., &metric);
+        let collision = match ray.trace(&metric, &scene, 100, 0.5, true, false).outcome {
+            TraceOutcome::Escaped { collision, .. } => collision,
+            outcome => panic!("The ray should escape on the far side: {:?}", outcome),
+        };
+        println!("Test final position : {:?}", ray.position);
+        assert!(collision.collision_point[1] < -19.);
+        assert_eq!(collision.color, far_side);
+    }
+
+    #[test]
+    fn trace_outcomes() {
+        let metric = Schwarzschild { rs: 1.0, c: 1.0 };
+        let scene = Scene {
+            obstacles: Vec::from([
+                Obstacle::BlackHole { r: 1. },
+                Obstacle::MaxDistance { r: 50. },
+                Obstacle::Ring {
+                    r_min: 3.,
+                    r_max: 20.,
+                    temperature: 3000.,
+                },
+            ]),
+            background: Background::Uniform(Rgb([1., 2., 3.])),
+            redshift: Redshift::Off,
+        };
+        let position = Vector4([0., 30., 1., 0.5]);
+        let trace = |orientation: [f64; 2], number_steps: i32| {
+            let mut ray = Ray::new_i(0.5, &position, &orientation, 1., &metric);
+            let result = ray.trace(&metric, &scene, number_steps, 0.5, false, false);
+            assert_eq!(result.position, ray.scene_position(&metric));
+            assert_eq!(
+                result.position_derivative,
+                ray.scene_position_derivative(&metric)
+            );
+            result
+        };
+
+        // Straight into the black hole
+        let result = trace([PI, 0.], 1000);
+        assert!(matches!(result.outcome, TraceOutcome::Captured(_)));
+        // Straight out, escaping radially
+        let result = trace([0., 0.], 1000);
+        match result.outcome {
+            TraceOutcome::Escaped {
+                collision,
+                direction,
+            } => {
+                assert_eq!(collision.color, Rgb([1., 2., 3.]));
+                assert!((collision.collision_point[1] - 50.).abs() < 0.5);
+                assert!((direction[0] - 1.).abs() < 1e-6 && (direction[1] - 0.5).abs() < 1e-6);
+            }
+            outcome => panic!("The ray should escape: {:?}", outcome),
+        }
+        // The affine parameter grows by the step size, and dr/dlambda is the
+        // constant energy along a radial ray
+        assert!((result.affine_parameter - result.steps as f64 * 0.5).abs() < 1e-9);
+        let energy = (1. - metric.rs / 30.).sqrt();
+        assert!((result.affine_parameter - 20. / energy).abs() < 0.5);
+        // Down onto the ring
+        let result = trace([PI - 0.6, 0.], 1000);
+        match result.outcome {
+            TraceOutcome::Hit(collision) => {
+                assert!((collision.collision_point[2] - PI / 2.).abs() < 1e-6);
+                assert!((3. ..=20.).contains(&collision.collision_point[1]));
+            }
+            outcome => panic!("The ray should hit the ring: {:?}", outcome),
+        }
+        // Out of steps, still moving radially outwards above the camera
+        let result = trace([0., 0.], 10);
+        assert_eq!(result.outcome, TraceOutcome::BudgetExhausted);
+        assert_eq!(result.steps, 10);
+        assert!((result.affine_parameter - 5.).abs() < 1e-9);
+        assert!((result.position[2] - 1.).abs() < 1e-9 && (result.position[3] - 0.5).abs() < 1e-9);
+        assert!(result.position_derivative[1] > 0.);
+        assert!(result.position_derivative[2].abs() < 1e-9);
+        assert!(result.position_derivative[3].abs() < 1e-9);
+        // From nowhere
+        let mut ray = Ray::new_i(0.5, &position, &[0., 0.], 1., &metric);
+        ray.position[1] = f64::NAN;
+        let result = ray.trace(&metric, &scene, 10, 0.5, false, false);
+        assert_eq!(result.outcome, TraceOutcome::NumericalFailure);
+        assert_eq!(result.steps, 1);
+    }
+
+    #[test]
+    fn disk_redshift() {
+        let metric = Schwarzschild { rs: 1.0, c: 1.0 };
+        let mut scene = Scene {
+            obstacles: Vec::from([
+                Obstacle::BlackHole { r: 1. },
+                Obstacle::MaxDistance { r: 33. },
+                Obstacle::Ring {
+                    r_min: 3.,
+                    r_max: 20.,
+                    temperature: 3000.,
+                },
+            ]),
+            background: Background::default(),
+            redshift: Redshift::Bolometric,
+        };
+        let position = Vector4([0., 30., 1., 0.5]);
+        let trace = |orientation: [f64; 2], scene: &Scene| {
+            let mut ray = Ray::new_i(0.1, &position, &orientation, 1., &metric);
+            match ray.trace(&metric, scene, 5000, 0.1, false, false).outcome {
+                TraceOutcome::Hit(collision) => collision,
+                outcome => panic!("The ray should hit the ring: {:?}", outcome),
+            }
+        };
+        let ring = scene.obstacles[2].clone();
+
+        // Light moving in the meridian plane of the camera has no angular
+        // momentum along the axis: only the gravitational redshift and the
+        // time dilation of the orbiting gas remain, at the collision point
+        let collision = trace([PI - 0.6, 0.], &scene);
+        let r = collision.collision_point[1];
+        let expected = (1. - 1.5 * metric.rs / r).sqrt() / (1. - metric.rs / 30.).sqrt();
+        let color = ring.shifted_color(&collision.collision_point, expected, 4);
+        for c in 0..3 {
+            assert!((collision.color[c] - color[c]).abs() <= 1e-6 * color[c] + 1e-9);
+        }
+        // Light emitted by the camera's own observer is not shifted
+        let ray = Ray::new_i(0.1, &position, &[PI - 0.6, 0.], 1., &metric);
+        assert!((ray.redshift(&metric, &Observer::Zamo) - 1.).abs() < 1e-12);
+
+        // The gas orbits towards +phi, approaching the camera on its -e_phi
+        // side, which is brighter and bluer than the receding one
+        let (approaching, receding) = ([PI - 0.35, -PI / 2.], [PI - 0.35, PI / 2.]);
+        let approaching_color = trace(approaching, &scene);
+        let receding_color = trace(receding, &scene);
+        let bluer = |color: &Rgb<f64>| color[2] / color[0];
+        assert!(luminance(&approaching_color.color) > luminance(&receding_color.color));
+        assert!(bluer(&approaching_color.color) > bluer(&receding_color.color));
+        // compared with the unshifted disk
+        scene.redshift = Redshift::Off;
+        let unshifted = trace(approaching, &scene);
+        assert!(luminance(&approaching_color.color) > luminance(&unshifted.color));
+        let unshifted = trace(receding, &scene);
+        assert!(luminance(&receding_color.color) < luminance(&unshifted.color));
+
+        // A table replays the same shifts, for a moving camera as well
+        scene.redshift = Redshift::Bolometric;
+        let table = DeflectionTable::from_photon_orbits(&metric, &scene, 30., 4096, PI / 128.);
+        let g = metric.components(&position);
+        let rest = Observer::Zamo.tetrad(&metric, &position);
+        for observer in [Observer::Zamo, Observer::FreeFall] {
+            let tetrad = observer.tetrad(&metric, &position);
+            for orientation in [approaching, receding, [PI - 0.4, 0.]] {
+                let mut ray = Ray::from_tetrad(&position, &orientation, &tetrad, &metric);
+                let traced = ray.trace(&metric, &scene, 5000, 0.1, false, false).outcome;
+                let (rest_orientation, energy) = change_frame(&g, &tetrad, &rest, &orientation);
+                let replayed = table.trace(&metric, &position, &rest_orientation, &scene, energy);
+                match (&traced, &replayed) {
+                    (TraceOutcome::Hit(traced), TraceOutcome::Hit(replayed)) => {
+                        for c in 0..3 {
+                            let difference = (traced.color[c] - replayed.color[c]).abs();
+                            assert!(difference <= 0.02 * traced.color[c] + 1.);
+                        }
+                    }
+                    _ => panic!("Both rays should hit the ring: {:?} {:?}", traced, replayed),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flat_straight_line() {
+        let metric = Minkowski;
+        let scene = Scene::default();
+        let mut position = Vector4::zeros();
+        position[1] = 10.;
+        position[2] = PI / 2.;
+        let mut orientation = [0.; 2];
+        orientation[0] = PI / 2.;
+        orientation[1] = PI / 2.;
+
+        // Starts at (10, 0, 0) heading towards +y: ends at (10, 10, 0)
+        let mut ray = Ray::new_i(0.1, &position, &orientation, 1., &metric);
+        ray.trace(&metric, &scene, 100, 0.1, true, false);
+        println!("Test final position : {:?}", ray.position);
+        assert!((ray.position[0] + 10.).abs() < 1e-9);
+        assert!((ray.position[1] - 10. * 2_f64.sqrt()).abs() < 1e-6);
+        assert!((ray.position[2] - PI / 2.).abs() < 1e-9);
+        assert!((ray.position[3] - PI / 4.).abs() < 1e-6);
+
+        // Out of the equator, the result is given in the scene's coordinates,
+        // not in those of the orbit plane the ray is traced in
+        let position = Vector4([0., 10., 1., 0.5]);
+        let mut ray = Ray::new_i(0.1, &position, &[2., 1.], 1., &metric);
+        let result = ray.trace(&metric, &scene, 100, 0.1, false, false);
+        let (sin, cos) = (1_f64.sin(), 1_f64.cos());
+        let (e_r, e_theta, e_phi) = (
+            [sin * 0.5_f64.cos(), sin * 0.5_f64.sin(), cos],
+            [cos * 0.5_f64.cos(), cos * 0.5_f64.sin(), -sin],
+            [-0.5_f64.sin(), 0.5_f64.cos(), 0.],
+        );
+        // Straight line from 10 e_r in the direction of the orientation
+        let velocity = [0, 1, 2].map(|i| {
+            2_f64.cos() * e_r[i] + 2_f64.sin() * (e_theta[i] * 1_f64.cos() + e_phi[i] * 1_f64.sin())
+        });
+        let end = [0, 1, 2].map(|i| 10. * e_r[i] + result.affine_parameter * velocity[i]);
+        let r = (end[0] * end[0] + end[1] * end[1] + end[2] * end[2]).sqrt();
+        let (theta, phi) = ((end[2] / r).acos(), end[1].atan2(end[0]));
+        assert!((result.position[1] - r).abs() < 1e-6);
+        assert!((result.position[2] - theta).abs() < 1e-6);
+        assert!((result.position[3] - phi).abs() < 1e-6);
+        let e_theta = [
+            theta.cos() * phi.cos(),
+            theta.cos() * phi.sin(),
+            -theta.sin(),
+        ];
+        let e_phi = [-phi.sin(), phi.cos(), 0.];
+        let projection =
+            |u: &[f64; 3]| u[0] * velocity[0] + u[1] * velocity[1] + u[2] * velocity[2];
+        assert!((result.position_derivative[1] - projection(&end) / r).abs() < 1e-6);
+        assert!((result.position_derivative[2] - projection(&e_theta) / r).abs() < 1e-6);
+        assert!(
+            (result.position_derivative[3] - projection(&e_phi) / (r * theta.sin())).abs() < 1e-6
+        );
+    }
+
+    #[test]
+    fn sky_background() {
+        // Pixel (i, j) of the panorama has the color (30 i, 60 j, 100)
+        let image = ImageBuffer::from_fn(8, 4, |i, j| Rgb([30 * i as u8, 60 * j as u8, 100]));
+        let sky = SkyTexture::new(&image);
+        let center = |i: f64, j: f64| (PI * (j + 0.5) / 4., 2. * PI * (i + 0.5) / 8. - PI);
+        let (theta, phi) = center(2., 1.);
+        assert_eq!(sky.color(theta, phi), Rgb([60., 60., 100.]));
+        // Bilinear between pixel centers, wrapping around in longitude
+        let (theta, phi) = center(2.5, 1.5);
+        assert!((sky.color(theta, phi)[0] - 75.).abs() < 1e-9);
+        assert!((sky.color(theta, phi)[1] - 90.).abs() < 1e-9);
+        let (theta, _) = center(0., 1.);
+        assert!((sky.color(theta, PI)[0] - 105.).abs() < 1e-9);
+        assert!((sky.color(theta, -PI)[0] - 105.).abs() < 1e-9);
+
+        // Without gravity, a ray escapes in its initial direction
+        let metric = Minkowski;
+        let scene = Scene {
+            obstacles: Vec::from([Obstacle::MaxDistance { r: 100. }]),
+            background: Background::Sky(sky.clone()),
+            redshift: Redshift::Off,
+        };
+        let position = Vector4([0., 10., PI / 2., 0.]);
+        for orientation in [[0.3, 0.2], [PI / 2., PI / 2.], [2., -1.], [3., 2.5]] {
+            let mut ray = Ray::new_i(0.1, &position, &orientation, 1., &metric);
+            let (color, escape) = match ray.trace(&metric, &scene, 5000, 0.1, false, false).outcome
+            {
+                TraceOutcome::Escaped {
+                    collision,
+                    direction,
+                } => (collision.color, direction),
+                outcome => panic!("The ray should escape: {:?}", outcome),
+            };
+            // e_r, e_theta and e_phi are x, -z and y
+            let (sin, cos) = orientation[0].sin_cos();
+            let direction = [cos, sin * orientation[1].sin(), -sin * orientation[1].cos()];
+            let (theta, phi) = (direction[2].acos(), direction[1].atan2(direction[0]));
+            assert!((escape[0] - theta).abs() < 1e-6 && (escape[1] - phi).abs() < 1e-6);
+            let expected = sky.color(theta, phi);
+            assert!((0..3).all(|c| (color[c] - expected[c]).abs() < 1e-3));
+        }
+        assert_eq!(
+            escape_direction(&Vector4([0., 10., 1., 2.]), &Vector4([1., 11., 1., 2.]))
+                .map(|a| (a * 1e12).round() / 1e12),
+            [1., 2.]
+        );
+    }
+
+    #[test]
+    fn procedural_backgrounds() {
+        // The starfield only depends on its seed
+        let starfield = Starfield::new(7, 10000, 1e-2);
+        assert_eq!(starfield, Starfield::new(7, 10000, 1e-2));
+        assert_ne!(starfield.stars, Starfield::new(8, 10000, 1e-2).stars);
+        assert!(starfield
+            .stars
+            .iter()
+            .all(|star| (0. ..=6.).contains(&star.magnitude)));
+        let bright = starfield.stars.iter().filter(|star| star.magnitude < 4.);
+        assert!((800..1200).contains(&bright.count()));
+
+        // A star is a gaussian spot
+        let single = Starfield::new(3, 1, 1e-2);
+        let star = single.stars[0];
+        let (theta, phi) = (
+            star.direction[2].acos(),
+            star.direction[1].atan2(star.direction[0]),
+        );
+        assert_eq!(single.color(theta, phi), star.color);
+        let spot = single.color(theta + 2e-2, phi);
+        assert!(
+            (0..3).all(|c| (spot[c] - star.color[c] * (-2_f64).exp()).abs() < 1e-3 * star.color[c])
+        );
+        assert_eq!(single.color(theta + 5e-2, phi), Rgb([0.; 3]));
+
+        // Looking the stars up by cell finds all of them
+        for i in 0..1000 {
+            let (theta, phi) = (PI * (i as f64 * 0.618).fract(), 0.37 * i as f64);
+            let direction = [
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ];
+            let mut expected = [0.; 3];
+            for star in starfield.stars.iter() {
+                let distance: f64 = (0..3)
+                    .map(|j| (direction[j] - star.direction[j]).powi(2))
+                    .sum();
+                if distance < 16. * starfield.size.powi(2) {
+                    let weight = (-distance / (2. * starfield.size.powi(2))).exp();
+                    for (c, star_color) in expected.iter_mut().zip(star.color.0) {
+                        *c += weight * star_color;
+                    }
+                }
+            }
+            let color = starfield.color(theta, phi);
+            assert!((0..3).all(|c| (color[c] - expected[c]).abs() < 1e-9));
+        }
+
+        let position = Vector4([0., 100., PI / 2., 0.]);
+        let (light, dark) = (Rgb([200.; 3]), Rgb([60.; 3]));
+        let checkerboard = Background::Checkerboard {
+            size: PI / 18.,
+            colors: [light, dark],
+        };
+        assert_eq!(checkerboard.color(&position, &[0.05, 0.05]), light);
+        assert_eq!(checkerboard.color(&position, &[0.05, 0.25]), dark);
+        assert_eq!(checkerboard.color(&position, &[0.25, -0.05]), light);
+        let grid = Background::Grid {
+            spacing: PI / 18.,
+            width: 1e-2,
+            color: light,
+        };
+        assert_eq!(grid.color(&position, &[PI / 6. + 4e-3, 1.]), light);
+        assert_eq!(grid.color(&position, &[1., PI / 3. - 4e-3]), light);
+        assert_eq!(grid.color(&position, &[1., 1.]), Rgb([0.; 3]));
+    }
+
+    #[test]
+    fn pole_crossing() {
+        let metric = Minkowski;
+        let scene = Scene::default();
+        // Starts just next to the north pole, heading over it
+        let theta = 1e-3;
+        let mut position = Vector4::zeros();
+        position[1] = 10.;
+        position[2] = theta;
+        let mut orientation = [0.; 2];
+        orientation[0] = PI / 2.;
+        orientation[1] = PI;
+
+        let mut ray = Ray::new_i(0.1, &position, &orientation, 1., &metric);
+        assert!(ray.rotation.is_some());
+        ray.trace(&metric, &scene, 200, 0.1, true, false);
+        let scene_position = ray.scene_position(&metric);
+        println!("Test final position : {:?}", scene_position);
+
+        // Straight line from 10 (sin theta, 0, cos theta) along -e_theta
+        let expected = [
+            10. * theta.sin() - 20. * theta.cos(),
+            0.,
+            10. * theta.cos() + 20. * theta.sin(),
+        ];
+        let r = scene_position[1];
+        let (sin, cos) = scene_position[2].sin_cos();
+        assert!((r * sin * scene_position[3].cos() - expected[0]).abs() < 1e-6);
+        assert!((r * sin * scene_position[3].sin() - expected[1]).abs() < 1e-6);
+        assert!((r * cos - expected[2]).abs() < 1e-6);
+        assert!((ray.position[2] - PI / 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn cosmological_spacetimes() {
+        // Kottler: an outgoing ray stops on the cosmological horizon and sees the sky
+        let sky = Rgb::<f64>([0., 0., 255.]);
+        let metric = SchwarzschildDeSitter {
+            rs: 1.0,
+            lambda: 0.01,
+        };
+        let cosmological_horizon = metric.cosmological_horizon_radius().unwrap();
+        let scene = Scene {
+            obstacles: Vec::from([Obstacle::cosmological_horizon(&metric).unwrap()]),
+            background: Background::Uniform(sky),
+            redshift: Redshift::Off,
+        };
+        let mut position = Vector4::zeros();
+        position[1] = 5.;
+        position[2] = PI / 2.;
+        let orientation = [0.; 2];
+        let mut ray = Ray::new_i(0.2, &position, &orientation, 1., &metric);
+        let collision = match ray.trace(&metric, &scene, 200, 0.2, true, false).outcome {
+            TraceOutcome::Escaped { collision, .. } => collision,
+            outcome => panic!(
+                "The ray should reach the cosmological horizon: {:?}",
+                outcome
+            ),
+        };
+        assert!(ray.position[1] >= 0.99 * cosmological_horizon);
+        assert!(ray.position[1] < cosmological_horizon);
+        assert_eq!(collision.color, sky);
+
+        // FLRW: light travels on straight lines in comoving coordinates, and
+        // comes from the past, where the universe was smaller
+        let metric = Flrw {
+            hubble_constant: 0.01,
+            w: 0.,
+        };
+        let scene = Scene {
+            obstacles: Vec::from([Obstacle::cosmological_horizon(&metric).unwrap()]),
+            background: Background::Uniform(sky),
+            redshift: Redshift::Off,
+        };
+        position[1] = 10.;
+        let mut orientation = [0.; 2];
+        orientation[0] = PI / 2.;
+        orientation[1] = PI / 2.;
+        let mut ray = Ray::new_i(0.1, &position, &orientation, 1., &metric);
+        ray.trace(&metric, &scene, 100, 0.1, true, false);
+        println!("Test final position : {:?}", ray.position);
+        assert!((ray.position[1] * ray.position[3].cos() - 10.).abs() < 1e-6);
+        assert!(ray.position[3] > 0.5);
+        assert!(metric.scale_factor(ray.position[0]) < 1.);
+
+        // Traced further back, it reaches the big bang and sees the sky
+        let collision = match ray.trace(&metric, &scene, 1000, 10., true, false).outcome {
+            TraceOutcome::Escaped { collision, .. } => collision,
+            outcome => panic!("The ray should reach the big bang: {:?}", outcome),
+        };
+        assert!(ray.position[0] <= 0.99 * metric.big_bang_time().unwrap());
+        assert_eq!(collision.color, sky);
+    }
+
+    #[test]
+    fn deflection_table() {
+        let metric = Schwarzschild { rs: 1., c: 1. };
+        let scene = Scene {
+            obstacles: Vec::from([
+                Obstacle::BlackHole { r: 1. },
+                Obstacle::MaxDistance { r: 33. },
+                Obstacle::Ring {
+                    r_min: 3.,
+                    r_max: 20.,
+                    temperature: 3000.,
+                },
+            ]),
+            background: Background::default(),
+            redshift: Redshift::Off,
+        };
+        let integrator = Rk4 {
+            adaptive_step: true,
+        };
+        let table = DeflectionTable::new(
+            &metric,
+            &scene,
+            30.,
+            1024,
+            PI / 128.,
+            2000,
+            0.4,
+            &integrator,
+        )
+        .expect("Schwarzschild is static and spherically symmetric");
+
+        // Inward rays are captured below the critical impact parameter
+        // 3 sqrt(3) rs / 2, outward ones all escape
+        let critical = 3. * 3_f64.sqrt() / 2.;
+        for path in &table.paths {
+            if path.emission_angle > PI / 2. && path.impact_parameter < 0.98 * critical {
+                assert_eq!(path.fate, Fate::Captured);
+            } else if path.impact_parameter > 1.02 * critical {
+                assert!(matches!(path.fate, Fate::Escaped { deflection } if deflection > 0.));
+            }
+        }
+
+        // Replaying the table gives the same image as tracing the rays
+        let mut position = Vector4::zeros();
+        position[1] = 30.;
+        position[2] = PI * 0.455;
+        let (mut rays, mut matching_rays) = (0, 0);
+        for i in 0..20 {
+            for j in 0..10 {
+                let orientation = [PI + 0.02 * (i + 1) as f64, 2. * PI * j as f64 / 10.];
+                let mut ray = Ray::new_i(0.4, &position, &orientation, 1., &metric);
+                let traced = ray.trace_with(&metric, &scene, 2000, 0.4, &integrator, false);
+                let replayed = table.trace(&metric, &position, &orientation, &scene, 1.);
+                rays += 1;
+                match (traced.outcome.collision(), replayed.collision()) {
+                    (None, None) => matching_rays += 1,
+                    (Some(traced), Some(replayed)) => {
+                        let difference = (0..3)
+                            .map(|c| (traced.color[c] - replayed.color[c]).abs())
+                            .fold(0., f64::max);
+                        if difference <= 5. {
+                            matching_rays += 1;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        println!("{} rays out of {} match", matching_rays, rays);
+        assert!(matching_rays as f64 >= 0.95 * rays as f64);
+    }
+
+    #[test]
+    fn photon_orbit() {
+        let metric = Schwarzschild { rs: 1., c: 1. };
+        let integrator = DormandPrince {
+            absolute_tolerance: 1e-10,
+            relative_tolerance: 1e-10,
+        };
+        let mut position = Vector4::zeros();
+        position[1] = 30.;
+        position[2] = PI / 2.;
+
+        // Outward, scattered and plunging rays follow the closed form orbits
+        for emission_angle in [0.3 * PI, 0.8 * PI, 0.97 * PI] {
+            let orbit = PhotonOrbit::new(&metric, 30., emission_angle);
+            let orientation = [emission_angle, PI / 2.];
+            let mut ray = Ray::new_i(0.4, &position, &orientation, 1., &metric);
+            let mut error: f64 = 0.;
+            for _ in 0..500 {
+                ray.trace_with(&metric, &Scene::default(), 1, 0.4, &integrator, false);
+                if ray.position[1] < 1.1 || ray.position[3] > orbit.end_angle {
+                    break;
+                }
+                let radius = orbit.radius(ray.position[3]);
+                error = error.max((ray.position[1] - radius).abs() / radius);
+            }
+            println!(
+                "Emission angle {} : relative error {}",
+                emission_angle, error
+            );
+            assert!(error < 1e-6);
+        }
+
+        // A ray seen from above the disk hits the ring where its orbit crosses
+        // the equator plane
+        let scene = Scene {
+            obstacles: Vec::from([
+                Obstacle::BlackHole { r: 1. },
+                Obstacle::MaxDistance { r: 33. },
+                Obstacle::Ring {
+                    r_min: 3.,
+                    r_max: 20.,
+                    temperature: 3000.,
+                },
+            ]),
+            background: Background::default(),
+            redshift: Redshift::Off,
+        };
+        position[2] = PI * 0.455;
+        let orientation = [PI + 0.15, PI / 2.];
+        let mut ray = Ray::new_i(0.4, &position, &orientation, 1., &metric);
+        let collision = match ray
+            .trace_with(&metric, &scene, 2000, 0.4, &integrator, false)
+            .outcome
+        {
+            TraceOutcome::Hit(collision) => collision,
+            outcome => panic!("The ray should hit the ring: {:?}", outcome),
+        };
+        let emission_angle = OrbitPlane::new(&position, &orientation).emission_angle;
+        let orbit = PhotonOrbit::new(&metric, 30., emission_angle);
+        let [_, radius, phi] = orbit
+            .equator_crossings(&position, &orientation)
+            .into_iter()
+            .find(|[_, r, _]| (3. ..=20.).contains(r))
+            .expect("The orbit should cross the ring");
+        println!(
+            "Crossing at r = {}, phi = {} : {:?}",
+            radius, phi, collision
+        );
+        assert!((collision.collision_point[1] - radius).abs() < 1e-2 * radius);
+        assert!((collision.collision_point[3] - phi).abs() < 1e-2);
+
+        // Inward rays are captured below the critical impact parameter
+        let table = DeflectionTable::from_photon_orbits(&metric, &scene, 30., 256, PI / 128.);
+        let critical = 3. * 3_f64.sqrt() / 2.;
+        for path in &table.paths {
+            let captured = path.emission_angle > PI / 2. && path.impact_parameter < critical;
+            assert_eq!(path.fate == Fate::Captured, captured);
+        }
+
+        // No observer stays at rest inside the horizon, and the elliptic
+        // integrals of its orbits are not numbers
+        assert!(std::panic::catch_unwind(|| PhotonOrbit::new(&metric, 0.5, PI / 2.)).is_err());
+        assert!(carlson_rf(f64::NAN, 1., 1.).is_nan());
+        assert!((carlson_rf(0., 1., 1.) - PI / 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn camera_orientation() {
+        let direction = |[theta, phi]: [f64; 2]| {
+            [
+                theta.cos(),
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+            ]
+        };
+        let distance =
+            |u: [f64; 3], v: [f64; 3]| (0..3).map(|i| (u[i] - v[i]).abs()).fold(0., f64::max);
+        let mut camera = Camera::new();
+        camera.position[0] = 30.;
+
+        // Without pitch nor yaw, the camera looks at the black hole
+        let (cx, cy): (f64, f64) = (0.1, -0.05);
+        for roll in [0., 0.7] {
+            camera.orientation[2] = roll;
+            let legacy = [
+                PI + (cx * cx + cy * cy).sqrt(),
+                PI / 2. + roll + f64::atan2(cy, cx),
+            ];
+            let orientation = camera.ray_orientation([cx, cy]);
+            assert!(distance(direction(orientation), direction(legacy)) < 1e-12);
+        }
+
+        // Turning around looks outwards, looking up looks at the north pole
+        camera.orientation[2] = 0.;
+        camera.orientation[1] = PI;
+        assert!(distance(direction(camera.ray_orientation([0., 0.])), [1., 0., 0.]) < 1e-12);
+        camera.orientation[0] = PI / 2.;
+        camera.orientation[1] = 0.;
+        assert!(distance(direction(camera.ray_orientation([0., 0.])), [0., -1., 0.]) < 1e-12);
+        // Then the right of the image is unchanged, the top looks outwards
+        let right = camera.ray_orientation([0.1, 0.]);
+        assert!(distance(direction(right), [0., -(0.1_f64.cos()), -(0.1_f64.sin())]) < 1e-12);
+        let top = camera.ray_orientation([0., -0.1]);
+        assert!(distance(direction(top), [0.1_f64.sin(), -(0.1_f64.cos()), 0.]) < 1e-12);
+    }
+
+    #[test]
+    fn observer_tetrads() {
+        let metric = Schwarzschild { rs: 1.0, c: 1.0 };
+        let position = Vector4([0., 10., PI / 2., 0.]);
+        let g = metric.components(&metric.from_spherical(&position));
+        let observers = [
+            Observer::Static,
+            Observer::Zamo,
+            Observer::FreeFall,
+            Observer::CircularOrbit { prograde: true },
+            Observer::CircularOrbit { prograde: false },
+            Observer::Velocity(Vector4([2., -0.1, 0.01, 0.02])),
+        ];
+        // Every tetrad is orthonormal, its first vector being the 4-velocity
+        for observer in observers.iter() {
+            let tetrad = observer.tetrad(&metric, &position);
+            let velocity = observer.velocity(&metric, &position);
+            assert!((0..4).all(|i| (tetrad[0][i] - velocity[i]).abs() < 1e-12));
+            for a in 0..4 {
+                for b in 0..4 {
+                    let eta = match (a, b) {
+                        (0, 0) => -1.,
+                        _ if a == b => 1.,
+                        _ => 0.,
+                    };
+                    let product = tetrad[a].dot(&g.dot(&tetrad[b]));
+                    assert!((product - eta).abs() < 1e-9, "{:?} {} {}", observer, a, b);
+                }
+            }
+        }
+        // Without rotation, the zero angular momentum observer is static
+        let (rest, zamo) = (
+            Observer::Static.tetrad(&metric, &position),
+            Observer::Zamo.tetrad(&metric, &position),
+        );
+        assert!((0..4).all(|a| (0..4).all(|i| (rest[a][i] - zamo[a][i]).abs() < 1e-12)));
+
+        // The free-falling observer moves inwards at v = sqrt(rs / r) relative
+        // to the static one: looking inwards, it sees the light blueshifted
+        let speed = (metric.rs / position[1]).sqrt();
+        let falling = Observer::FreeFall.tetrad(&metric, &position);
+        let energy = |frame: &[Vector4; 4], orientation: [f64; 2]| {
+            Ray::from_tetrad(&position, &orientation, frame, &metric)
+                .constants_of_motion(&metric)
+                .energy
+        };
+        let shift = energy(&falling, [PI, 0.]) / energy(&rest, [PI, 0.]);
+        assert!((shift - ((1. - speed) / (1. + speed)).sqrt()).abs() < 1e-9);
+        // and the sky aberrated towards its direction of motion
+        for angle in [0.1, 0.5, 1., 1.5, 2., 3.] {
+            let ([theta, _], _) = change_frame(&g, &falling, &rest, &[PI - angle, 0.3]);
+            let expected = (angle.cos() - speed) / (1. - speed * angle.cos());
+            assert!(((PI - theta).cos() - expected).abs() < 1e-9);
+        }
+        // the same shift as between the energies of the rays
+        let (_, doppler) = change_frame(&g, &falling, &rest, &[PI, 0.]);
+        assert!((doppler - shift).abs() < 1e-9);
+        let (orientation, energy) = change_frame(&g, &rest, &rest, &[1., 0.3]);
+        assert_eq!(orientation.map(|a| (a * 1e9).round()), [1e9, 3e8]);
+        assert!((energy - 1.).abs() < 1e-12);
+
+        // Circular geodesics follow Kepler's third law
+        let mass = metric.rs / 2.;
+        let velocity = Observer::CircularOrbit { prograde: true }.velocity(&metric, &position);
+        let omega = velocity[3] / velocity[0];
+        assert!((omega - (mass / position[1].powi(3)).sqrt()).abs() < 1e-7);
+        // and its Kerr version, slower in the direction of the rotation
+        let kerr = Kerr { rs: 1.0, a: 0.4 };
+        for (prograde, sign) in [(true, 1.), (false, -1.)] {
+            let velocity = Observer::CircularOrbit { prograde }.velocity(&kerr, &position);
+            let expected =
+                sign * mass.sqrt() / (position[1].powf(1.5) + sign * kerr.a * mass.sqrt());
+            assert!((velocity[3] / velocity[0] - expected).abs() < 1e-7);
+        }
+
+        // The free-falling observer crosses the horizon of horizon penetrating
+        // coordinates, where no observer can be static
+        let kerr_schild = KerrSchild { rs: 1.0, a: 0.4 };
+        let inside = Vector4([0., 0.8, PI / 3., 0.5]);
+        let tetrad = Observer::FreeFall.tetrad(&kerr_schild, &inside);
+        assert!(tetrad.iter().all(|e| (0..4).all(|i| e[i].is_finite())));
+        assert!(Observer::Static.velocity(&kerr_schild, &inside)[0].is_nan());
+    }
+
+    #[test]
+    fn framebuffer_formats() {
+        let hdr = vec![
+            Rgb([0., 0., 0.]),
+            Rgb([1e-3, 2e-3, 5e-4]),
+            Rgb([1., 0.5, 0.25]),
+            Rgb([3e4, 1e4, 2e4]),
+            Rgb([7., 7., 7.]),
+            Rgb([0.1, 20., 300.]),
+        ];
+        let linear = ToneMapping {
+            operator: Operator::Linear,
+            exposure: Exposure::Brightest(1.),
+            gamma: 1.,
+        };
+        let framebuffer = Framebuffer::new(3, 2, hdr.clone(), &linear);
+        // The 8-bit image is normalized by the brightest pixel
+        assert_eq!(framebuffer.image[(0, 1)], Rgb([255, 85, 170]));
+        assert_eq!(framebuffer.image[(1, 0)], Rgb([0, 0, 0]));
+        assert_eq!(framebuffer.hdr_pixel(2, 1), hdr[5]);
+
+        let directory = std::env::temp_dir();
+        let pfm = directory.join(format!("framebuffer_{}.pfm", std::process::id()));
+        framebuffer.save(&pfm).expect("Problem on saving image");
+        let bytes = std::fs::read(&pfm).expect("Problem on reading image");
+        std::fs::remove_file(&pfm).expect("Problem on removing image");
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let floats: Vec<f32> = bytes[header.len()..]
+            .chunks(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        assert_eq!(floats.len(), 3 * 6);
+        // Rows from the bottom to the top
+        for (i, pixel) in hdr[3..].iter().chain(&hdr[..3]).enumerate() {
+            for c in 0..3 {
+                assert_eq!(floats[3 * i + c], pixel[c] as f32);
+            }
+        }
+
+        let radiance = directory.join(format!("framebuffer_{}.hdr", std::process::id()));
+        framebuffer
+            .save(&radiance)
+            .expect("Problem on saving image");
+        let file = std::io::BufReader::new(
+            std::fs::File::open(&radiance).expect("Problem on reading image"),
+        );
+        let decoded = HdrDecoder::new(file)
+            .and_then(|decoder| decoder.read_image_hdr())
+            .expect("Problem on decoding image");
+        std::fs::remove_file(&radiance).expect("Problem on removing image");
+        // The mantissas are relative to the brightest channel of each pixel
+        for (decoded, pixel) in decoded.iter().zip(&hdr) {
+            let scale = pixel[0].max(pixel[1]).max(pixel[2]);
+            for c in 0..3 {
+                assert!((decoded[c] as f64 - pixel[c]).abs() <= 1e-2 * scale);
+            }
+        }
+
+        // OpenEXR is not among the formats
+        let exr = directory.join(format!("framebuffer_{}.exr", std::process::id()));
+        assert!(framebuffer.save(&exr).is_err());
+        assert!(!exr.exists());
+    }
+
+    #[test]
+    fn tone_mapping() {
+        // A dim frame with a single firefly
+        let mut hdr: Vec<Rgb<f64>> = (0..100)
+            .map(|i| Rgb([i as f64, 0.5 * i as f64, 0.25 * i as f64]))
+            .collect();
+        hdr[42] = Rgb([1e6; 3]);
+        let mut framebuffer = Framebuffer::new(10, 10, hdr.clone(), &ToneMapping::default());
+        // Scaled by the brightest pixel, the frame is black
+        assert!(framebuffer
+            .image
+            .pixels()
+            .enumerate()
+            .all(|(i, pixel)| i == 42 || pixel.0 == [0; 3]));
+        assert_eq!(framebuffer.image[(2, 4)], Rgb([255; 3]));
+        // The automatic exposure ignores it
+        let auto = ToneMapping {
+            operator: Operator::Linear,
+            exposure: Exposure::Percentile {
+                percentile: 0.9,
+                key: 1.,
+            },
+            gamma: 1.,
+        };
+        framebuffer.tone_map(&auto);
+        let scale = auto.exposure.scale(&hdr);
+        assert!((scale * luminance(&hdr[90]) - 1.).abs() < 1e-12);
+        assert_eq!(
+            framebuffer.image[(0, 5)],
+            Rgb([
+                (50. * scale * 255.) as u8,
+                (25. * scale * 255.) as u8,
+                (12.5 * scale * 255.) as u8
+            ])
+        );
+        assert_eq!(framebuffer.image[(9, 9)][0], 255);
+
+        // A fixed exposure is the same for every frame
+        let fixed = ToneMapping {
+            operator: Operator::Linear,
+            exposure: Exposure::Fixed(scale),
+            gamma: 1.,
+        };
+        let mut brighter = hdr.clone();
+        brighter[42] = Rgb([1e9; 3]);
+        assert_eq!(
+            Framebuffer::new(10, 10, brighter, &fixed).image,
+            framebuffer.image
+        );
+
+        // Every operator is increasing, from black to at most white
+        let ramp: Vec<Rgb<f64>> = (0..1000).map(|i| Rgb([0.01 * i as f64; 3])).collect();
+        for operator in [
+            Operator::Linear,
+            Operator::Reinhard,
+            Operator::Aces,
+            Operator::Logarithmic,
+        ] {
+            let tone_mapping = ToneMapping {
+                operator,
+                exposure: Exposure::Fixed(1.),
+                gamma: 1.,
+            };
+            let colors = tone_mapping.apply(&ramp);
+            assert_eq!(colors[0], Rgb([0; 3]));
+            assert!(colors.windows(2).all(|pair| pair[0][0] <= pair[1][0]));
+            let expected_white = match operator {
+                Operator::Reinhard => (9.99 / 10.99 * 255.) as u8,
+                _ => 255,
+            };
+            assert_eq!(colors[999][0], expected_white, "{:?}", operator);
+        }
+        // Reinhard maps 1 to half, the filmic curve keeps its midtones
+        let reinhard = ToneMapping {
+            operator: Operator::Reinhard,
+            exposure: Exposure::Fixed(1.),
+            gamma: 1.,
+        };
+        assert_eq!(reinhard.apply(&[Rgb([1.; 3])])[0], Rgb([127; 3]));
+        let aces = ToneMapping {
+            operator: Operator::Aces,
+            ..reinhard
+        };
+        assert_eq!(aces.apply(&[Rgb([0.18; 3])])[0], Rgb([68; 3]));
+
+        // A black frame stays black, whatever the exposure
+        let black = vec![Rgb([0.; 3]); 4];
+        for (exposure, scale) in [
+            (ToneMapping::default().exposure, 0.),
+            (Exposure::Fixed(1.), 1.),
+        ] {
+            assert_eq!(exposure.scale(&black), scale);
+            let logarithmic = ToneMapping {
+                operator: Operator::Logarithmic,
+                exposure,
+                gamma: 1.,
+            };
+            assert_eq!(logarithmic.apply(&black), vec![Rgb([0; 3]); 4]);
+        }
+    }
+
     #[test]
     fn test_image_plot() {
         let _result = match std::fs::remove_file("test.png") {
@@ -180,11 +1428,11 @@ mod unit_tests {
         let max_radius = Obstacle::MaxDistance {
             r: camera_distance * 1.1,
         };
-        let mut space = Space {
-            rs: 100.0,
-            c: 1.0,
-            christoffel: Array3::zeros((4, 4, 4)),
+        let metric = Schwarzschild { rs: 100.0, c: 1.0 };
+        let scene = Scene {
             obstacles: Vec::from([blackhole, max_radius, accretionDisk]),
+            background: Background::default(),
+            redshift: Redshift::Off,
         };
 
         let mut cam_position = Array1::<f64>::zeros(3);
@@ -199,8 +1447,36 @@ mod unit_tests {
             im_size: [200, 100],
             orientation: cam_orientation,
             position: cam_position,
+            observer: Observer::default(),
         };
 
-        camera.render(4, 1000, 40., &mut space, 2.5, 0.75);
+        let (framebuffer, statistics) = camera.render(
+            4,
+            1000,
+            40.,
+            &Rk4 {
+                adaptive_step: true,
+            },
+            &metric,
+            &scene,
+            &ToneMapping::default(),
+        );
+        assert_eq!(framebuffer.image.dimensions(), (200, 100));
+        assert_eq!(framebuffer.hdr.len(), 200 * 100);
+        // The black hole is in the center, and the brightest pixel is white
+        assert_eq!(framebuffer.hdr_pixel(100, 50), Rgb([0.; 3]));
+        assert_eq!(framebuffer.image[(100, 50)], Rgb([0; 3]));
+        assert!(framebuffer
+            .image
+            .pixels()
+            .any(|pixel| pixel.0.contains(&255)));
+        // Every sub-ray of every pixel is counted once
+        let rays = statistics.hit
+            + statistics.captured
+            + statistics.escaped
+            + statistics.failed
+            + statistics.exhausted;
+        assert_eq!(rays, 4 * 200 * 100);
+        assert!(statistics.captured > 0 && statistics.accepted_steps > 0);
     }
 }
@@ -0,0 +1,131 @@
+use crate::{
+    ray::{local_frame, observer_frame},
+    space::Metric,
+    tensor::{Matrix4, Vector4},
+};
+
+/// Observer carrying the camera. The directions of the rays are measured in
+/// its orthonormal frame (tetrad), so that a moving camera sees the
+/// aberration and the Doppler shift of the light it receives:
+/// - Static: at rest with respect to the coordinates, along d/dt. It cannot
+///   exist inside an ergosphere or a horizon.
+/// - Zamo: zero angular momentum observer, whose world line is normal to the
+///   t = const hypersurfaces (see `local_frame`). It is the static observer
+///   of non-rotating spacetimes, and the default one.
+/// - FreeFall: falling radially from rest at infinity, with a unit energy per
+///   unit mass and no angular momentum. It crosses the horizon in horizon
+///   penetrating coordinates such as `KerrSchild`.
+/// - CircularOrbit: on the circular geodesic through its position, in the
+///   equator plane, in the direction of the rotation of the black hole or
+///   against it. It cannot exist inside the photon sphere.
+/// - Velocity: any 4-velocity, in the metric's coordinates, normalized.
+///
+/// Where the observer cannot exist, its tetrad is not a number.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Observer {
+    Static,
+    Zamo,
+    FreeFall,
+    CircularOrbit { prograde: bool },
+    Velocity(Vector4),
+}
+
+impl Default for Observer {
+    fn default() -> Self {
+        Observer::Zamo
+    }
+}
+
+impl Observer {
+    /// 4-velocity of the observer at the position (t, r, theta, phi), in the
+    /// metric's coordinates.
+    pub fn velocity<M: Metric>(&self, metric: &M, position: &Vector4) -> Vector4 {
+        let coordinates = metric.from_spherical(position);
+        let g = metric.components(&coordinates);
+        let jacobian = metric.spherical_jacobian(position);
+        let velocity = match self {
+            Observer::Static => jacobian.column(0),
+            Observer::Zamo => {
+                let g_inv = metric.inverse(&coordinates);
+                g_inv.row(0) / -(-g_inv[[0, 0]]).sqrt()
+            }
+            Observer::FreeFall => {
+                // Radial velocity v relative to the zero angular momentum
+                // observer, such that the energy -u.d/dt is 1
+                let frame = local_frame(&g, &metric.inverse(&coordinates), &jacobian);
+                let time = jacobian.column(0);
+                let lapse = -frame[0].dot(&g.dot(&time));
+                let shift = frame[1].dot(&g.dot(&time));
+                let v = (lapse * shift - (1. + shift * shift - lapse * lapse).sqrt())
+                    / (1. + shift * shift);
+                (frame[0] + frame[1] * v) / (1. - v * v).sqrt()
+            }
+            Observer::CircularOrbit { prograde } => {
+                // The angular velocity of circular geodesics solves
+                // d/dr (g_tt + 2 omega g_tphi + omega² g_phiphi) = 0
+                let step = 1e-6 * position[1];
+                let components = |r: f64| {
+                    let mut position = *position;
+                    position[1] = r;
+                    let g = metric.components(&metric.from_spherical(&position));
+                    let jacobian = metric.spherical_jacobian(&position);
+                    let (time, phi) = (jacobian.column(0), jacobian.column(3));
+                    [
+                        time.dot(&g.dot(&time)),
+                        time.dot(&g.dot(&phi)),
+                        phi.dot(&g.dot(&phi)),
+                    ]
+                };
+                let (outer, inner) = (
+                    components(position[1] + step),
+                    components(position[1] - step),
+                );
+                let [c, b, a] = [0, 1, 2].map(|i| (outer[i] - inner[i]) / (2. * step));
+                let root = (b * b - a * c).sqrt();
+                let omega = (-b + if *prograde { root } else { -root }) / a;
+                jacobian.column(0) + jacobian.column(3) * omega
+            }
+            Observer::Velocity(velocity) => *velocity,
+        };
+        velocity / (-velocity.dot(&g.dot(&velocity))).sqrt()
+    }
+
+    /// Orthonormal frame (e_t, e_r, e_theta, e_phi) of the observer at the
+    /// position (t, r, theta, phi), see `observer_frame`.
+    pub fn tetrad<M: Metric>(&self, metric: &M, position: &Vector4) -> [Vector4; 4] {
+        let coordinates = metric.from_spherical(position);
+        observer_frame(
+            &metric.components(&coordinates),
+            &self.velocity(metric, position),
+            &metric.spherical_jacobian(position),
+        )
+    }
+}
+
+/// Direction (theta, phi) in the tetrad 'to' of the ray leaving in the
+/// direction 'orientation' of the tetrad 'from', see `Ray::new_i`: the
+/// aberration between two observers at the same event, of metric 'g'. Also
+/// returns the energy of the ray in 'to' divided by its energy in 'from',
+/// the Doppler shift between them.
+pub fn change_frame(
+    g: &Matrix4,
+    from: &[Vector4; 4],
+    to: &[Vector4; 4],
+    orientation: &[f64; 2],
+) -> ([f64; 2], f64) {
+    let (theta, phi) = (orientation[0], orientation[1]);
+    let velocity = -from[0]
+        + from[1] * theta.cos()
+        + from[2] * (theta.sin() * phi.cos())
+        + from[3] * (theta.sin() * phi.sin());
+    let lowered = g.dot(&velocity);
+    let energy = lowered.dot(&to[0]);
+    let direction = [1, 2, 3].map(|i| lowered.dot(&to[i]) / energy);
+    (
+        [
+            direction[0].clamp(-1., 1.).acos(),
+            direction[2].atan2(direction[1]),
+        ],
+        energy,
+    )
+}
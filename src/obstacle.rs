@@ -1,5 +1,5 @@
+use crate::{space::Metric, tensor::Vector4};
 use image::Rgb;
-use ndarray::Array1;
 use noise::{HybridMulti, MultiFractal, NoiseFn, Seedable};
 use rand::prelude::*;
 use std::f64::consts::PI;
@@ -13,12 +13,19 @@ use std::f64::consts::PI;
 ///
 ///     - BlackHole: A collision is triggered whenever the current position is
 ///             inside the event horizon (radial coordinate <= black hole
-///             radius). The color is pure black (0,0,0).
+///             radius). The color is pure black (0,0,0). 'black_hole()' sizes
+///             it on the outer horizon of a metric.
 ///     - BlackHolePredict: Triggered whenever the direction deduced by the
 ///             two positions points towards the black hole's disk. This helps
 ///             to save useless computation by stopping a path early.
 ///     - MaxDistance: Triggered if the distance to the origin exceeds an upper
-///             limit given by the 'r' parameter. Color is (0,0,0).
+///             limit given by the 'r' parameter, on either side of a wormhole
+///             throat. Color is (0,0,0), the tracer replaces it with the
+///             space's background. 'cosmological_horizon()' places it on the
+///             cosmological horizon of a metric.
+///     - BigBang: Triggered when a ray traced back in time reaches the time
+///             coordinate 't', shortly after the big bang of an expanding
+///             universe. Like MaxDistance, shows the space's background.
 ///     - Ring: Flat ring placed on the equator plane (theta = PI/2 in spherical
 ///             coordinates), bounded by its inner radius 'r_min' and outer
 ///             radius 'r_max'. Collision is triggered when the path crosses
@@ -41,6 +48,9 @@ pub enum Obstacle {
     MaxDistance {
         r: f64,
     }, // Ray cannot escape max distance from origin
+    BigBang {
+        t: f64,
+    }, // Ray cannot be traced back before time t
     Ring {
         r_min: f64,
         r_max: f64,
@@ -55,7 +65,7 @@ pub enum Obstacle {
 }
 #[derive(Debug, Clone, PartialEq)]
 pub struct CollisionPoint {
-    pub collision_point: Array1<f64>,
+    pub collision_point: Vector4,
     pub color: Rgb<f64>,
 }
 /// (Temporary) Conversion table for black body radiation temperature to
@@ -85,11 +95,16 @@ static BLACKBODY_INTERP: [[f64; 3]; 21] = [
     [0.4009, 0.5630, 1.0000],
     [0.3928, 0.5565, 1.0000],
 ];
+/// Color of the disk at 'ray_pos', seen with the redshift factor 'redshift'
+/// (see `Ray::redshift`): the temperature of the black body is multiplied by
+/// it, and its intensity by its power 'beaming'.
 pub fn accretion_texture(
     r_min: &f64,
     _r_max: &f64,
-    ray_pos: &Array1<f64>,
+    ray_pos: &Vector4,
     max_temperature: f64,
+    redshift: f64,
+    beaming: i32,
 ) -> Rgb<f64> {
     let random_gen = HybridMulti::default()
         .set_frequency(1.)
@@ -114,28 +129,45 @@ pub fn accretion_texture(
     if ray_pos[1] <= *r_min {
         blackbodylum = 0.;
     }
-    let temp = blackbodylum.powf(0.25) * max_temperature / 1000.;
-    let interp = temp - temp.floor();
-    Rgb::<f64>([
-        ((BLACKBODY_INTERP[temp.floor() as usize][0] * (1. - interp)
-            + BLACKBODY_INTERP[(temp.floor() + 1.) as usize][0] * interp)
-            * blackbodylum
-            * 255.),
-        ((BLACKBODY_INTERP[temp.floor() as usize][1] * (1. - interp)
-            + BLACKBODY_INTERP[(temp.floor() + 1.) as usize][1] * interp)
-            * blackbodylum
-            * 255.),
-        ((BLACKBODY_INTERP[temp.floor() as usize][2] * (1. - interp)
-            + BLACKBODY_INTERP[(temp.floor() + 1.) as usize][2] * interp)
-            * blackbodylum
-            * 255.),
-    ])
+    let color = blackbody(redshift * blackbodylum.powf(0.25) * max_temperature);
+    let intensity = blackbodylum * redshift.powi(beaming);
+    Rgb::<f64>(color.map(|c| c * intensity * 255.))
+}
+
+/// Color of a black body at the given temperature in Kelvin, interpolated
+/// in `BLACKBODY_INTERP`, between 0 and 1, up to 20000 Kelvin.
+pub fn blackbody(temperature: f64) -> [f64; 3] {
+    let temp = (temperature / 1000.).clamp(0., 20.);
+    let index = (temp.floor() as usize).min(19);
+    let interp = temp - index as f64;
+    [0, 1, 2].map(|i| {
+        BLACKBODY_INTERP[index][i] * (1. - interp) + BLACKBODY_INTERP[index + 1][i] * interp
+    })
 }
 impl Obstacle {
+    /// BlackHole obstacle sized on the outer horizon of the given metric, if
+    /// the spacetime has one.
+    pub fn black_hole<M: Metric>(metric: &M) -> Option<Obstacle> {
+        metric.horizon_radius().map(|r| Obstacle::BlackHole { r })
+    }
+
+    /// Obstacle bounding what can be seen in the given metric, if the
+    /// spacetime has a cosmological horizon: a MaxDistance placed just inside
+    /// the horizon, where the coordinates are still regular, or a BigBang
+    /// shortly after the big bang.
+    pub fn cosmological_horizon<M: Metric>(metric: &M) -> Option<Obstacle> {
+        if let Some(r) = metric.cosmological_horizon_radius() {
+            return Some(Obstacle::MaxDistance { r: 0.99 * r });
+        }
+        metric
+            .big_bang_time()
+            .map(|t| Obstacle::BigBang { t: 0.99 * t })
+    }
+
     pub fn collision(
         &self,
-        ray_pos_t: &Array1<f64>,
-        ray_pos_t_plus_dt: &Array1<f64>,
+        ray_pos_t: &Vector4,
+        ray_pos_t_plus_dt: &Vector4,
         step_size: f64,
     ) -> f64 {
         match self {
@@ -147,7 +179,14 @@ impl Obstacle {
                 }
             }
             Obstacle::MaxDistance { r } => {
-                if ray_pos_t_plus_dt[1] >= *r {
+                if ray_pos_t_plus_dt[1].abs() >= *r {
+                    0.
+                } else {
+                    -1.
+                }
+            }
+            Obstacle::BigBang { t } => {
+                if ray_pos_t_plus_dt[0] <= *t {
                     0.
                 } else {
                     -1.
@@ -267,22 +306,29 @@ impl Obstacle {
             }
         }
     }
-    pub fn color(&self, ray_pos: &Array1<f64>) -> Rgb<f64> {
+    pub fn color(&self, ray_pos: &Vector4) -> Rgb<f64> {
+        self.shifted_color(ray_pos, 1., 0)
+    }
+
+    /// Same as `color`, for the light emitted by the disks with the given
+    /// redshift factor and beaming exponent, see `accretion_texture`.
+    pub fn shifted_color(&self, ray_pos: &Vector4, redshift: f64, beaming: i32) -> Rgb<f64> {
         match self {
             Obstacle::BlackHole { r: _ } => Rgb::<f64>([0., 0., 0.]),
             Obstacle::BlackHolePredict { r: _ } => Rgb::<f64>([0., 0., 0.]),
             Obstacle::MaxDistance { r: _ } => Rgb::<f64>([0., 0., 0.]),
+            Obstacle::BigBang { t: _ } => Rgb::<f64>([0., 0., 0.]),
             Obstacle::Ring {
                 r_min,
                 r_max,
                 temperature,
-            } => accretion_texture(r_min, r_max, ray_pos, *temperature),
+            } => accretion_texture(r_min, r_max, ray_pos, *temperature, redshift, beaming),
             Obstacle::AccretionDisk {
                 r_min,
                 r_max,
                 thickness: _,
                 temperature,
-            } => accretion_texture(r_min, r_max, ray_pos, *temperature),
+            } => accretion_texture(r_min, r_max, ray_pos, *temperature, redshift, beaming),
         }
     }
 }
@@ -0,0 +1,247 @@
+use crate::{deflection::OrbitPlane, space::Schwarzschild, tensor::Vector4};
+use std::f64::consts::PI;
+
+/// Light ray of a Schwarzschild black hole of mass M = rs / 2, solved in
+/// closed form in its orbit plane.
+///
+/// The inverse radius u = 1 / r of a photon follows d²u/dphi² = 3 M u² - u,
+/// whose first integral
+///     (du/dphi)² = 2 M u³ - u² + 1 / b² = 2 M (u - u1) (u - u2) (u - u3)
+/// only depends on the impact parameter b. It is inverted with the Jacobi
+/// elliptic functions sn and cn of parameter m, of an argument w growing
+/// linearly with the orbit angle phi along the ray, depending on the roots:
+/// - Scattered: b > 3 sqrt(3) M, outside the photon sphere, u1 < 0 <= u <= u2:
+///   u = u1 + (u2 - u1) sn²(w, m), m = (u2 - u1) / (u3 - u1).
+/// - Trapped: b > 3 sqrt(3) M, inside the photon sphere, u >= u3:
+///   u = (u3 - u2 sn²(w, m)) / cn²(w, m), with the same m.
+/// - Plunging: b < 3 sqrt(3) M, one real root u1 and two complex ones
+///   c +- i d: u = u1 + A (1 - cn(w, m)) / (1 + cn(w, m)),
+///   A² = (c - u1)² + d², m = (A + c - u1) / (2 A).
+/// These are the real forms of u = 1 / (6 M) + 2 / M P(phi - phi0) with
+/// the Weierstrass function P of invariants g2 = 1 / 12 and
+/// g3 = 1 / 216 - M² / (4 b²).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhotonOrbit {
+    pub mass: f64,
+    pub impact_parameter: f64, // b = L / E
+    pub emission_angle: f64,   // to the outward radial direction, see `Ray::new_i`
+    pub end_angle: f64,        // orbit angle where the ray reaches infinity or the horizon
+    pub captured: bool,        // whether the ray ends in the black hole
+    shape: Shape,
+    phase: f64, // elliptic argument w at the observer
+    rate: f64,  // dw / dphi
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    Radial { u: f64 },
+    Scattered { u1: f64, u2: f64, m: f64 },
+    Trapped { u2: f64, u3: f64, m: f64 },
+    Plunging { u1: f64, a: f64, m: f64 },
+}
+
+impl PhotonOrbit {
+    /// Orbit of the ray leaving the observer at rest at 'observer_radius'
+    /// with the given emission angle. The orbit angle is 0 at the observer
+    /// and grows along the ray, which is traced backwards in time like in
+    /// `Ray::trace`. Panics for an observer on or inside the horizon, where
+    /// no observer can stay at rest.
+    pub fn new(metric: &Schwarzschild, observer_radius: f64, emission_angle: f64) -> Self {
+        assert!(
+            observer_radius > metric.rs,
+            "Photon orbit seen from radius {}, inside the horizon {}!",
+            observer_radius,
+            metric.rs
+        );
+        let mass = metric.rs / 2.;
+        let u0 = 1. / observer_radius;
+        let u_horizon = 1. / metric.rs;
+        let impact_parameter =
+            observer_radius * emission_angle.sin() / (1. - metric.rs / observer_radius).sqrt();
+        let inward = emission_angle > PI / 2.;
+        let mut orbit = PhotonOrbit {
+            mass,
+            impact_parameter,
+            emission_angle,
+            end_angle: 0.,
+            captured: inward,
+            shape: Shape::Radial { u: u0 },
+            phase: 0.,
+            rate: 1.,
+        };
+        if impact_parameter < 1e-12 * observer_radius {
+            return orbit;
+        }
+
+        // Roots of u³ - u² / (2 M) + 1 / (2 M b²), from the depressed cubic
+        // x³ + p x + q in x = u - 1 / (6 M)
+        let shift = 1. / (6. * mass);
+        let p = -1. / (12. * mass * mass);
+        let q = -1. / (108. * mass.powi(3)) + 1. / (2. * mass * impact_parameter.powi(2));
+        let discriminant = q * q / 4. + p.powi(3) / 27.;
+        let (phase, end_phase) = if discriminant < 0. {
+            let amplitude = 2. * (-p / 3.).sqrt();
+            let angle = ((3. * q / (2. * p)) * (-3. / p).sqrt())
+                .clamp(-1., 1.)
+                .acos()
+                / 3.;
+            let mut roots =
+                [0., 1., 2.].map(|k| amplitude * (angle - 2. * PI * k / 3.).cos() + shift);
+            roots.sort_by(|a, b| a.partial_cmp(b).expect("Roots are not NaN"));
+            let [u1, u2, u3] = roots;
+            let m = (u2 - u1) / (u3 - u1);
+            orbit.rate = (mass * (u3 - u1) / 2.).sqrt();
+            if u0 <= (u2 + u3) / 2. {
+                orbit.shape = Shape::Scattered { u1, u2, m };
+                orbit.captured = false;
+                let amplitude = |u: f64| (((u - u1) / (u2 - u1)).clamp(0., 1.)).sqrt().asin();
+                let period = 2. * elliptic_f(PI / 2., m);
+                let start = elliptic_f(amplitude(u0), m);
+                // sn² grows until the perihelion at w = K, then decreases
+                let phase = if inward { start } else { period - start };
+                (phase, period - elliptic_f(amplitude(0.), m))
+            } else {
+                orbit.shape = Shape::Trapped { u2, u3, m };
+                orbit.captured = true;
+                let amplitude = |u: f64| (((u - u3) / (u - u2)).clamp(0., 1.)).sqrt().asin();
+                // Symmetric around the aphelion at w = 0
+                let start = elliptic_f(amplitude(u0), m);
+                let phase = if inward { start } else { -start };
+                (phase, elliptic_f(amplitude(u_horizon), m))
+            }
+        } else {
+            let root =
+                (-q / 2. + discriminant.sqrt()).cbrt() + (-q / 2. - discriminant.sqrt()).cbrt();
+            let u1 = root + shift;
+            let (c, d) = (
+                -root / 2. + shift,
+                (3. * root * root / 4. + p).max(0.).sqrt(),
+            );
+            let a = ((c - u1).powi(2) + d * d).sqrt();
+            let m = (a + c - u1) / (2. * a);
+            orbit.shape = Shape::Plunging { u1, a, m };
+            orbit.rate = (2. * mass * a).sqrt();
+            let amplitude = |u: f64| ((a - (u - u1)) / (a + (u - u1))).clamp(-1., 1.).acos();
+            // u grows from u1 at w = 0 to infinity at w = 2 K
+            let start = elliptic_f(amplitude(u0), m);
+            if inward {
+                (start, elliptic_f(amplitude(u_horizon), m))
+            } else {
+                (-start, -elliptic_f(amplitude(0.), m))
+            }
+        };
+        orbit.phase = phase;
+        orbit.end_angle = (end_phase - phase) / orbit.rate;
+        orbit
+    }
+
+    /// Radius at the given orbit angle, between 0 and `end_angle`.
+    pub fn radius(&self, orbit_angle: f64) -> f64 {
+        let w = self.phase + self.rate * orbit_angle;
+        let u = match self.shape {
+            Shape::Radial { u } => u,
+            Shape::Scattered { u1, u2, m } => {
+                let [sn, _, _] = jacobi_elliptic(w, m);
+                u1 + (u2 - u1) * sn * sn
+            }
+            Shape::Trapped { u2, u3, m } => {
+                let [sn, cn, _] = jacobi_elliptic(w, m);
+                (u3 - u2 * sn * sn) / (cn * cn)
+            }
+            Shape::Plunging { u1, a, m } => {
+                let [_, cn, _] = jacobi_elliptic(w, m);
+                u1 + a * (1. - cn) / (1. + cn)
+            }
+        };
+        1. / u
+    }
+
+    /// Points (orbit angle, r, phi) where the ray leaving 'position'
+    /// (t, r, theta, phi) in the direction 'orientation' (see `Ray::new_i`)
+    /// crosses the equator plane theta = PI / 2, where `Obstacle::Ring` and
+    /// `Obstacle::AccretionDisk` lie, in order along the ray. The orbit must be
+    /// built for the radius and the emission angle of that ray, see
+    /// `OrbitPlane`. Rays moving in the equator plane never cross it.
+    pub fn equator_crossings(&self, position: &Vector4, orientation: &[f64; 2]) -> Vec<[f64; 3]> {
+        let plane = OrbitPlane::new(position, orientation);
+        plane
+            .equator_crossings(self.end_angle)
+            .map(|orbit_angle| {
+                let (_, phi) = plane.direction(orbit_angle);
+                [orbit_angle, self.radius(orbit_angle), phi]
+            })
+            .collect()
+    }
+}
+
+/// Jacobi elliptic functions [sn, cn, dn] of argument u and parameter m,
+/// with 0 <= m <= 1, by the arithmetic-geometric mean (Abramowitz and
+/// Stegun 16.4).
+pub fn jacobi_elliptic(u: f64, m: f64) -> [f64; 3] {
+    if m >= 1. {
+        return [u.tanh(), 1. / u.cosh(), 1. / u.cosh()];
+    }
+    let mut a = [1.; 16];
+    let mut c = [m.sqrt(); 16];
+    let mut b = (1. - m).sqrt();
+    let mut n = 0;
+    while c[n].abs() > f64::EPSILON * a[n] && n < 15 {
+        a[n + 1] = (a[n] + b) / 2.;
+        c[n + 1] = (a[n] - b) / 2.;
+        b = (a[n] * b).sqrt();
+        n += 1;
+    }
+    if n == 0 {
+        return [u.sin(), u.cos(), 1.];
+    }
+    let mut phi = 2_f64.powi(n as i32) * a[n] * u;
+    let mut previous = phi;
+    for i in (1..=n).rev() {
+        previous = phi;
+        phi = (phi + (c[i] / a[i] * phi.sin()).asin()) / 2.;
+    }
+    [phi.sin(), phi.cos(), phi.cos() / (previous - phi).cos()]
+}
+
+/// Incomplete elliptic integral of the first kind F(amplitude, m), the
+/// inverse of the amplitude of the Jacobi functions, for any amplitude.
+pub fn elliptic_f(amplitude: f64, m: f64) -> f64 {
+    // F(x + j PI) = F(x) + 2 j K
+    let periods = (amplitude / PI).round();
+    let (sin, cos) = (amplitude - periods * PI).sin_cos();
+    if m >= 1. {
+        return if periods == 0. {
+            sin.atanh()
+        } else {
+            periods * f64::INFINITY
+        };
+    }
+    let incomplete = sin * carlson_rf(cos * cos, 1. - m * sin * sin, 1.);
+    if periods == 0. {
+        incomplete
+    } else {
+        2. * periods * carlson_rf(0., 1. - m, 1.) + incomplete
+    }
+}
+
+/// Carlson's symmetric elliptic integral R_F(x, y, z), by the duplication
+/// theorem, for non negative arguments at most one of which is zero. Other
+/// arguments, such as NaN, give NaN.
+pub fn carlson_rf(x: f64, y: f64, z: f64) -> f64 {
+    let (mut x, mut y, mut z) = (x, y, z);
+    // Each duplication divides the distance to the mean by 4
+    for _ in 0..64 {
+        let mean = (x + y + z) / 3.;
+        let (dx, dy, dz) = (1. - x / mean, 1. - y / mean, 1. - z / mean);
+        if dx.abs().max(dy.abs()).max(dz.abs()) < 0.0025 {
+            let e2 = dx * dy - dz * dz;
+            let e3 = dx * dy * dz;
+            return (1. + (e2 / 24. - 0.1 - 3. * e3 / 44.) * e2 + e3 / 14.) / mean.sqrt();
+        }
+        let lambda = (x * y).sqrt() + (y * z).sqrt() + (z * x).sqrt();
+        x = (x + lambda) / 4.;
+        y = (y + lambda) / 4.;
+        z = (z + lambda) / 4.;
+    }
+    f64::NAN
+}
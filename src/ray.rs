@@ -1,24 +1,128 @@
-use ndarray::Array1;
-
-use crate::{obstacle::CollisionPoint, space::Space};
+use crate::{
+    background::escape_direction,
+    integrator::{Integrator, Rk4},
+    observer::Observer,
+    obstacle::{CollisionPoint, Obstacle},
+    scene::{Scene, KEPLERIAN},
+    space::{CoordinateSystem, Metric},
+    tensor::{Matrix4, Vector4},
+};
 
 static _C: f64 = 1.;
 
+/// Cost of the integration of a ray, the scheme it was integrated with, and,
+/// if it is tracked (see `Ray::track_drift`), how far it drifted from its
+/// constants of motion. A large drift flags a pixel that is numerically
+/// unreliable.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TraceStatistics {
+    pub scheme: &'static str,
+    pub accepted_steps: usize,
+    pub rejected_steps: usize,
+    pub evaluations: usize, // of the Christoffel symbols, or of the metric for the drift
+    pub initial_constants: Option<ConstantsOfMotion>,
+    pub drift: Option<ConstantsOfMotion>, // largest absolute deviation from the initial constants
+}
+
+/// Quantities conserved along a geodesic, from the covariant momentum
+/// p_mu = g_{mu nu} dx^nu / dlambda:
+/// - energy: -p_t, conserved in stationary spacetimes.
+/// - angular_momentum: p_phi, or x p_y - y p_x in Cartesian coordinates,
+///   conserved in axisymmetric spacetimes. For rays traced in their orbit
+///   plane, this is the total angular momentum.
+/// - hamiltonian: g^{mu nu} p_mu p_nu / 2, always conserved, and zero for
+///   light (the null condition).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ConstantsOfMotion {
+    pub energy: f64,
+    pub angular_momentum: f64,
+    pub hamiltonian: f64,
+}
+
+/// Why the tracing of a ray stopped:
+/// - Hit: it met an obstacle of the scene.
+/// - Captured: it met a 'BlackHole' or 'BlackHolePredict' obstacle.
+/// - Escaped: it left the scene through a 'MaxDistance' or 'BigBang'
+///   obstacle, moving in the direction (theta, phi) of the scene given by
+///   'direction', see `escape_direction`. Its color is the background's.
+/// - NumericalFailure: the integration gave a position which is not a number.
+/// - BudgetExhausted: none of the above happened within the allowed steps.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceOutcome {
+    Hit(CollisionPoint),
+    Captured(CollisionPoint),
+    Escaped {
+        collision: CollisionPoint,
+        direction: [f64; 2],
+    },
+    NumericalFailure,
+    BudgetExhausted,
+}
+
+impl TraceOutcome {
+    /// Outcome of a ray stopped by the obstacle at the collision point,
+    /// moving in the direction (theta, phi).
+    pub fn new(obstacle: &Obstacle, collision: CollisionPoint, direction: [f64; 2]) -> Self {
+        match obstacle {
+            Obstacle::BlackHole { r: _ } | Obstacle::BlackHolePredict { r: _ } => {
+                TraceOutcome::Captured(collision)
+            }
+            Obstacle::MaxDistance { r: _ } | Obstacle::BigBang { t: _ } => TraceOutcome::Escaped {
+                collision,
+                direction,
+            },
+            _ => TraceOutcome::Hit(collision),
+        }
+    }
+
+    /// Where the ray met an obstacle, and the color it sees there, if it did.
+    pub fn collision(&self) -> Option<&CollisionPoint> {
+        match self {
+            TraceOutcome::Hit(collision)
+            | TraceOutcome::Captured(collision)
+            | TraceOutcome::Escaped {
+                collision,
+                direction: _,
+            } => Some(collision),
+            TraceOutcome::NumericalFailure | TraceOutcome::BudgetExhausted => None,
+        }
+    }
+}
+
+/// What `Ray::trace` returns: its outcome, the state of the ray at the end
+/// of its last step, in the (t, r, theta, phi) coordinates of the scene (see
+/// `Ray::scene_position` and `Ray::scene_position_derivative`), the number
+/// of steps it took, and the affine parameter it travelled over them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceResult {
+    pub outcome: TraceOutcome,
+    pub position: Vector4,
+    pub position_derivative: Vector4,
+    pub steps: usize,
+    pub affine_parameter: f64,
+}
+
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct Ray {
-    pub position: Array1<f64>,
-    pub position_derivative: Array1<f64>, // order of coordinates are t,r,theta,phi
+    pub position: Vector4,
+    pub position_derivative: Vector4, // in the metric's coordinates, usually t,r,theta,phi
+    pub rotation: Option<[[f64; 3]; 3]>, // from the orbit plane frame to the scene, see `new_i`
+    pub proposed_step: Option<f64>,   // next step size chosen by an adaptive integrator
+    pub statistics: TraceStatistics,
 }
 
 impl Ray {
     pub fn new() -> Self {
         // Initializes ray with zero arrays
         Ray {
-            position: Array1::<f64>::zeros(4),
-            position_derivative: Array1::<f64>::zeros(4),
+            position: Vector4::zeros(),
+            position_derivative: Vector4::zeros(),
+            rotation: None,
+            proposed_step: None,
+            statistics: TraceStatistics::default(),
         }
     }
-    ///Initializes a ray in given space and given integration step size with :
+    ///Initializes a ray in given metric and given integration step size with :
     /// - initial position,
     /// - initial movement direction (spherical coordinates in proper frame)
     ///        (0,_) points outwards
@@ -27,12 +131,24 @@ impl Ray {
     ///        (pi/2,pi/2) is tangent, follows the "latitudes"
     /// - initial velocity magnitude
     ///
-    pub fn new_i(
+    /// The direction is measured by the observer at rest with respect to the
+    /// t = const slices (see `local_frame` and `Observer::Zamo`), so
+    /// off-diagonal metrics such as Kerr get a null initial momentum as well.
+    /// Its energy in the observer's frame is 1. The ray is traced backwards
+    /// in time, from the camera to the light source, which matters in rotating
+    /// and expanding spacetimes. Metrics using other coordinates than
+    /// spherical ones convert the position and the directions to their own.
+    ///
+    /// In spherically symmetric metrics, the ray is traced in rotated
+    /// spherical coordinates where its orbit plane is the equator, so it never
+    /// comes near the coordinate singularity on the polar axis. Use
+    /// `scene_position` to get positions back in the scene's coordinates.
+    pub fn new_i<M: Metric>(
         _step_size: f64,
-        initial_position: &Array1<f64>,    // Size 4 (t, r, theta, phi)
-        initial_orientation: &Array1<f64>, // Size 2 (theta, phi)
+        initial_position: &Vector4,     // (t, r, theta, phi)
+        initial_orientation: &[f64; 2], // (theta, phi)
         _initial_velocity: f64,
-        space: &Space,
+        metric: &M,
     ) -> Self {
         /*
         if initial_velocity != 1.{
@@ -40,64 +156,289 @@ impl Ray {
             return Ray::new();
         }*/
 
-        let position = initial_position.clone();
-        let mut position_derivative = Array1::<f64>::zeros(4);
-        let metric = space.metric(&position);
-        position_derivative[0] = 1. / ((-metric[0]).sqrt());
-        position_derivative[1] = (initial_orientation[0].cos()) / (metric[1].sqrt());
-        position_derivative[2] =
-            (initial_orientation[0].sin()) * (initial_orientation[1].cos()) / (metric[2].sqrt());
-        position_derivative[3] =
-            (initial_orientation[0].sin()) * (initial_orientation[1].sin()) / (metric[3].sqrt());
+        Ray::observed(
+            initial_position,
+            initial_orientation,
+            &Observer::default(),
+            metric,
+        )
+    }
 
-        Ray {
+    /// Same as `new_i`, with the direction measured by the given observer,
+    /// whose motion changes the directions and the energies of the rays.
+    pub fn observed<M: Metric>(
+        initial_position: &Vector4,     // (t, r, theta, phi)
+        initial_orientation: &[f64; 2], // (theta, phi)
+        observer: &Observer,
+        metric: &M,
+    ) -> Self {
+        Ray::from_tetrad(
+            initial_position,
+            initial_orientation,
+            &observer.tetrad(metric, initial_position),
+            metric,
+        )
+    }
+
+    /// Same as `observed`, with the tetrad of the observer at the initial
+    /// position (see `Observer::tetrad`), shared by all the rays of a camera.
+    pub fn from_tetrad<M: Metric>(
+        initial_position: &Vector4,     // (t, r, theta, phi)
+        initial_orientation: &[f64; 2], // (theta, phi)
+        frame: &[Vector4; 4],
+        metric: &M,
+    ) -> Self {
+        let position = metric.from_spherical(initial_position);
+        let position_derivative = -frame[0]
+            + frame[1] * initial_orientation[0].cos()
+            + frame[2] * (initial_orientation[0].sin() * initial_orientation[1].cos())
+            + frame[3] * (initial_orientation[0].sin() * initial_orientation[1].sin());
+
+        let mut ray = Ray {
             position,
             position_derivative,
+            ..Ray::new()
+        };
+        if metric.spherically_symmetric() {
+            ray.rotate_to_orbit_plane();
         }
+        ray
     }
 
-    fn next_step(&mut self, d_lambda: f64, space: &Space) {
-        // Runge kutta 4 integration method, computes one step
-        let initial_position = &self.position;
-        let initial_position_derivative = &self.position_derivative;
+    /// Tracks the drift of the constants of motion of the ray in its
+    /// statistics over the following traces, from their values at the start
+    /// of the first one, at the cost of an evaluation of the metric per step.
+    pub fn track_drift(&mut self) {
+        if self.statistics.drift.is_none() {
+            self.statistics.drift = Some(ConstantsOfMotion::default());
+        }
+    }
 
-        let k1 = &second_derivative(initial_position, initial_position_derivative, space);
-        let k2 = &second_derivative(
-            &(initial_position + (initial_position_derivative * d_lambda / 2.)),
-            &(initial_position_derivative + (k1 * d_lambda / 2.)),
-            space,
-        );
-        let k3 = &second_derivative(
-            &(initial_position
-                + (initial_position_derivative * d_lambda / 2.)
-                + (k1 * (d_lambda.powf(2.)) / 4.)),
-            &(initial_position_derivative + (k2 * d_lambda / 2.)),
-            space,
+    pub fn constants_of_motion<M: Metric>(&self, metric: &M) -> ConstantsOfMotion {
+        let momentum = metric
+            .components(&self.position)
+            .dot(&self.position_derivative);
+        let angular_momentum = match metric.coordinate_system() {
+            CoordinateSystem::Cartesian => {
+                self.position[1] * momentum[2] - self.position[2] * momentum[1]
+            }
+            _ => momentum[3],
+        };
+        ConstantsOfMotion {
+            energy: -momentum[0],
+            angular_momentum,
+            hamiltonian: momentum.dot(&self.position_derivative) / 2.,
+        }
+    }
+
+    /// Position in the (t, r, theta, phi) coordinates of the scene, in which
+    /// the camera and the obstacles are placed.
+    pub fn scene_position<M: Metric>(&self, metric: &M) -> Vector4 {
+        self.to_scene(&self.position, metric)
+    }
+
+    /// Derivative dx^mu / dlambda of `scene_position`, in the (t, r, theta,
+    /// phi) coordinates of the scene.
+    pub fn scene_position_derivative<M: Metric>(&self, metric: &M) -> Vector4 {
+        let scene_position = self.scene_position(metric);
+        let derivative = self.position_derivative;
+        match (&self.rotation, metric.coordinate_system()) {
+            (Some(rotation), _) => {
+                let (theta, phi) = (self.position[2], self.position[3]);
+                let e_theta = [
+                    theta.cos() * phi.cos(),
+                    theta.cos() * phi.sin(),
+                    -theta.sin(),
+                ];
+                let e_phi = [-phi.sin(), phi.cos(), 0.];
+                let angular_velocity = rotate(
+                    rotation,
+                    &[0, 1, 2].map(|i| {
+                        e_theta[i] * derivative[2] + e_phi[i] * theta.sin() * derivative[3]
+                    }),
+                );
+                let (theta, phi) = (scene_position[2], scene_position[3]);
+                let e_theta = [
+                    theta.cos() * phi.cos(),
+                    theta.cos() * phi.sin(),
+                    -theta.sin(),
+                ];
+                let e_phi = [-phi.sin(), phi.cos(), 0.];
+                Vector4([
+                    derivative[0],
+                    derivative[1],
+                    dot(&angular_velocity, &e_theta),
+                    dot(&angular_velocity, &e_phi) / theta.sin(),
+                ])
+            }
+            (None, CoordinateSystem::Cartesian) => {
+                // Solves jacobian . d_scene = derivative by Cramer's rule,
+                // time being left unchanged by `to_spherical`
+                let jacobian = metric.spherical_jacobian(&scene_position);
+                let column = |j: usize| [jacobian[[1, j]], jacobian[[2, j]], jacobian[[3, j]]];
+                let (a, b, c) = (column(1), column(2), column(3));
+                let v = [derivative[1], derivative[2], derivative[3]];
+                let determinant = dot(&a, &cross(&b, &c));
+                Vector4([
+                    derivative[0],
+                    dot(&v, &cross(&b, &c)) / determinant,
+                    dot(&a, &cross(&v, &c)) / determinant,
+                    dot(&a, &cross(&b, &v)) / determinant,
+                ])
+            }
+            (None, _) => derivative,
+        }
+    }
+
+    /// Covariant momentum p_mu = g_{mu nu} dx^nu / dlambda of the ray at
+    /// `scene_position`, in the coordinates `Observer::velocity` uses there:
+    /// the metric's, or the (t, r, theta, phi) of the scene for the rays
+    /// traced in their orbit plane.
+    pub fn scene_momentum<M: Metric>(&self, metric: &M) -> Vector4 {
+        let momentum = metric
+            .components(&self.position)
+            .dot(&self.position_derivative);
+        match &self.rotation {
+            Some(rotation) => {
+                let (theta, phi) = (self.position[2], self.position[3]);
+                let angular_momentum = rotate(
+                    rotation,
+                    &angular_momentum(momentum[2], momentum[3], theta, phi),
+                );
+                let scene_position = self.scene_position(metric);
+                let [p_theta, p_phi] =
+                    angular_components(&angular_momentum, scene_position[2], scene_position[3]);
+                Vector4([momentum[0], momentum[1], p_theta, p_phi])
+            }
+            None => momentum,
+        }
+    }
+
+    /// Redshift factor g = E_observed / E_emitted of the light emitted by
+    /// 'emitter' at the current position of the ray, and received by the
+    /// observer it left. The ray being traced backwards, with an energy of 1
+    /// for that observer (see `new_i`), g = 1 / (p.u) for its momentum p and
+    /// the emitter's 4-velocity u. Above 1, the light is blueshifted.
+    pub fn redshift<M: Metric>(&self, metric: &M, emitter: &Observer) -> f64 {
+        let velocity = emitter.velocity(metric, &self.scene_position(metric));
+        1. / self.scene_momentum(metric).dot(&velocity)
+    }
+
+    fn to_scene<M: Metric>(&self, position: &Vector4, metric: &M) -> Vector4 {
+        match &self.rotation {
+            Some(rotation) => {
+                let direction = rotate(rotation, &unit_vector(position[2], position[3]));
+                let mut scene_position = *position;
+                scene_position[2] = direction[2].clamp(-1., 1.).acos();
+                scene_position[3] = direction[1].atan2(direction[0]);
+                scene_position
+            }
+            None => metric.to_spherical(position),
+        }
+    }
+
+    // Rotates the spherical coordinates so that the position and the velocity
+    // lie in the equator plane. The rotation is the identity for rays already
+    // moving in the equator plane.
+    fn rotate_to_orbit_plane(&mut self) {
+        let (theta, phi) = (self.position[2], self.position[3]);
+        let direction = unit_vector(theta, phi);
+        let e_theta = [
+            theta.cos() * phi.cos(),
+            theta.cos() * phi.sin(),
+            -theta.sin(),
+        ];
+        let e_phi = [-phi.sin(), phi.cos(), 0.];
+        // Angular velocity of the direction to the ray, orthogonal to it
+        let (theta_velocity, phi_velocity) = (
+            self.position_derivative[2],
+            theta.sin() * self.position_derivative[3],
         );
-        let k4 = second_derivative(
-            &(initial_position
-                + (initial_position_derivative * d_lambda)
-                + (k2 * (d_lambda.powf(2.)) / 2.)),
-            &(initial_position_derivative + (k3 * d_lambda)),
-            space,
+        let angular_velocity =
+            [0, 1, 2].map(|i| e_theta[i] * theta_velocity + e_phi[i] * phi_velocity);
+
+        let mut axis = cross(&direction, &angular_velocity);
+        if dot(&axis, &axis) < 1e-24 {
+            // Radial ray: any plane containing it will do
+            axis = orthogonal_part(&[0., 0., 1.], &direction);
+            if dot(&axis, &axis) < 1e-24 {
+                axis = [1., 0., 0.];
+            }
+        }
+        if axis[2] < 0. {
+            axis = axis.map(|x| -x);
+        }
+        let axis = normalized(&axis);
+        let mut x_axis = orthogonal_part(&[1., 0., 0.], &axis);
+        if dot(&x_axis, &x_axis) < 1e-24 {
+            x_axis = orthogonal_part(&[0., 1., 0.], &axis);
+        }
+        let x_axis = normalized(&x_axis);
+        let y_axis = cross(&axis, &x_axis);
+        let rotation = [0, 1, 2].map(|i| [x_axis[i], y_axis[i], axis[i]]);
+
+        // The inverse rotation is the transpose, whose rows are the axes
+        let direction = [
+            dot(&x_axis, &direction),
+            dot(&y_axis, &direction),
+            dot(&axis, &direction),
+        ];
+        let angular_velocity = [
+            dot(&x_axis, &angular_velocity),
+            dot(&y_axis, &angular_velocity),
+            dot(&axis, &angular_velocity),
+        ];
+        let theta = direction[2].clamp(-1., 1.).acos();
+        let phi = direction[1].atan2(direction[0]);
+        self.position[2] = theta;
+        self.position[3] = phi;
+        self.position_derivative[2] = dot(
+            &angular_velocity,
+            &[
+                theta.cos() * phi.cos(),
+                theta.cos() * phi.sin(),
+                -theta.sin(),
+            ],
         );
-        self.position = initial_position
-            + (d_lambda * initial_position_derivative)
-            + ((d_lambda.powf(2.)) / 6. * (k1 + k2 + k3));
-        self.position_derivative =
-            initial_position_derivative + (d_lambda / 6. * (k1 + (2. * k2) + (2. * k3) + k4));
-        //println!("Step size {}", d_lambda);
+        self.position_derivative[3] =
+            dot(&angular_velocity, &[-phi.sin(), phi.cos(), 0.]) / theta.sin();
+        self.rotation = Some(rotation);
     }
 
-    pub fn trace(
+    pub fn trace<M: Metric>(
         &mut self,
-        space: &Space,
+        metric: &M,
+        scene: &Scene,
         number_steps: i32,
         step_size: f64,
         adaptive_step: bool,
         verbose: bool,
-    ) -> Option<CollisionPoint> {
-        // Performs the number of calls to next_step() specified in argument
+    ) -> TraceResult {
+        self.trace_with(
+            metric,
+            scene,
+            number_steps,
+            step_size,
+            &Rk4 { adaptive_step },
+            verbose,
+        )
+    }
+
+    /// Same as `trace`, with the given integration scheme. Each of the
+    /// 'number_steps' steps is an accepted one. The scheme and the cost of
+    /// the integration are recorded in the ray's statistics.
+    pub fn trace_with<M: Metric>(
+        &mut self,
+        metric: &M,
+        scene: &Scene,
+        number_steps: i32,
+        step_size: f64,
+        integrator: &dyn Integrator<M>,
+        verbose: bool,
+    ) -> TraceResult {
+        // Performs the number of integration steps specified in argument
+        self.statistics.scheme = integrator.name();
+        self.update_drift(metric);
         if verbose {
             println!("-----Trace : {}-----", number_steps);
             print!("Initial state : ");
@@ -116,24 +457,14 @@ impl Ray {
                 dp = self.position_derivative[3]
             );
         }
+        // Obstacles are described in the scene's (t, r, theta, phi)
+        let mut old_scene_position = self.scene_position(metric);
+        let mut affine_parameter = 0.;
         for n in 0..number_steps {
-            let old_position = &self.position.clone();
-            let mut d_lambda = step_size;
-            if adaptive_step {
-                d_lambda = (step_size * (1. - space.rs / self.position[1]).abs())
-                    .max(space.rs * step_size / 200.);
-                let pole_orth_velocity = ((self.position[1] * self.position_derivative[2]).powi(2)
-                    + (self.position[1] * self.position_derivative[3] * (self.position[2]).sin())
-                        .powi(2))
-                .sqrt();
-
-                let pole_distance = self.position[1] * self.position[2].sin();
-                if pole_distance.abs() < step_size * pole_orth_velocity * 1.5 {
-                    //d_lambda = step_size.sqrt() * pole_distance / pole_orth_velocity / 10.;
-                    d_lambda = step_size.min(pole_distance / 20.);
-                }
-            }
-            self.next_step(d_lambda, space);
+            let (old_position, old_position_derivative) = (self.position, self.position_derivative);
+            let d_lambda = integrator.step(self, step_size, metric);
+            affine_parameter += d_lambda;
+            self.update_drift(metric);
             if verbose {
                 print!("\n\n* Step {} out of {}", n + 1, number_steps);
                 print!(
@@ -151,58 +482,204 @@ impl Ray {
                     dp = self.position_derivative[3]
                 );
                 println!("  -  Local step size : {}", d_lambda);
-                let distance = ((self.position[1] - old_position[1]).powi(2)
-                    / (1. - space.rs / self.position[1])
-                    + (self.position[2] - old_position[2]).powi(2) * (self.position[1].powi(2))
-                    + (self.position[3] - old_position[3]).powi(2)
-                        * ((self.position[1] * (self.position[2].sin())).powi(2)))
-                .sqrt();
-                println!("  -  Reference step size : {}", distance);
-                let momentum_conservation = -self.position_derivative[0].powi(2)
-                    * (1. - space.rs / self.position[1])
-                    / space.c.powi(2)
-                    + self.position_derivative[1].powi(2) / (1. - space.rs / self.position[1])
-                    + (self.position_derivative[2] * self.position[1]).powi(2)
-                    + (self.position_derivative[3] * self.position[1] * self.position[2].sin())
-                        .powi(2);
+                let g = metric.components(&self.position);
+                let displacement = self.position - old_position;
+                let mut distance = 0.;
+                let mut momentum_conservation = 0.;
+                for i in 0..4 {
+                    for j in 0..4 {
+                        if i > 0 && j > 0 {
+                            distance += g[[i, j]] * displacement[i] * displacement[j];
+                        }
+                        momentum_conservation +=
+                            g[[i, j]] * self.position_derivative[i] * self.position_derivative[j];
+                    }
+                }
+                println!("  -  Reference step size : {}", distance.sqrt());
                 println!("  -  Conservation of momentum = {}", momentum_conservation);
             }
-            let new_position = &self.position.clone();
             if f64::is_nan(self.position[1]) {
-                return None;
+                return self.result(
+                    metric,
+                    TraceOutcome::NumericalFailure,
+                    n + 1,
+                    affine_parameter,
+                );
             }
-            for obs in &space.obstacles {
-                let interpolation = obs.collision(old_position, new_position, d_lambda);
+            let new_scene_position = self.scene_position(metric);
+            for obs in &scene.obstacles {
+                let interpolation =
+                    obs.collision(&old_scene_position, &new_scene_position, d_lambda);
                 if interpolation >= 0. {
-                    let collision_position =
-                        new_position * interpolation + old_position * (1. - interpolation);
-                    return Some(CollisionPoint {
-                        collision_point: collision_position.clone(),
-                        color: obs.color(&collision_position),
-                    });
+                    // State of the ray at the collision, within the step
+                    let collision_ray = Ray {
+                        position: self.position * interpolation
+                            + old_position * (1. - interpolation),
+                        position_derivative: self.position_derivative * interpolation
+                            + old_position_derivative * (1. - interpolation),
+                        rotation: self.rotation,
+                        ..Ray::new()
+                    };
+                    let collision_position = collision_ray.scene_position(metric);
+                    let direction = escape_direction(&old_scene_position, &new_scene_position);
+                    let redshift = if scene.shifts(obs) {
+                        collision_ray.redshift(metric, &KEPLERIAN)
+                    } else {
+                        1.
+                    };
+                    let collision = CollisionPoint {
+                        collision_point: collision_position,
+                        color: scene.color(obs, &collision_position, &direction, redshift),
+                    };
+                    let outcome = TraceOutcome::new(obs, collision, direction);
+                    return self.result(metric, outcome, n + 1, affine_parameter);
                 }
             }
+            old_scene_position = new_scene_position;
+        }
+        let steps = number_steps.max(0);
+        self.result(
+            metric,
+            TraceOutcome::BudgetExhausted,
+            steps,
+            affine_parameter,
+        )
+    }
+
+    // Updates the largest drift of the constants of motion from their initial
+    // values, recorded at the first call, if it is tracked
+    fn update_drift<M: Metric>(&mut self, metric: &M) {
+        if let Some(mut drift) = self.statistics.drift {
+            let constants = self.constants_of_motion(metric);
+            self.statistics.evaluations += 1;
+            let initial_constants = *self.statistics.initial_constants.get_or_insert(constants);
+            drift.energy = drift
+                .energy
+                .max((constants.energy - initial_constants.energy).abs());
+            drift.angular_momentum = drift
+                .angular_momentum
+                .max((constants.angular_momentum - initial_constants.angular_momentum).abs());
+            drift.hamiltonian = drift
+                .hamiltonian
+                .max((constants.hamiltonian - initial_constants.hamiltonian).abs());
+            self.statistics.drift = Some(drift);
+        }
+    }
+
+    fn result<M: Metric>(
+        &self,
+        metric: &M,
+        outcome: TraceOutcome,
+        steps: i32,
+        affine_parameter: f64,
+    ) -> TraceResult {
+        TraceResult {
+            outcome,
+            position: self.scene_position(metric),
+            position_derivative: self.scene_position_derivative(metric),
+            steps: steps as usize,
+            affine_parameter,
         }
-        None
     }
 }
 
-fn second_derivative(
-    // Computes second derivative of movement at given position and velocity, in given space
-    position: &Array1<f64>,
-    position_derivative: &Array1<f64>,
-    space: &Space,
-) -> Array1<f64> {
-    let updated_space = space.update_christoffel(position);
-    let mut second_derivative = Array1::<f64>::zeros(4);
-    for i in 0..4 {
-        for j in 0..4 {
-            for k in 0..4 {
-                second_derivative[i] -= updated_space.christoffel[[i, j, k]]
-                    * position_derivative[j]
-                    * position_derivative[k];
-            }
+/// Orthonormal frame (e_t, e_r, e_theta, e_phi) of the observer whose world
+/// line is normal to the t = const hypersurfaces: the static observer for
+/// diagonal metrics, the zero angular momentum observer around a Kerr black
+/// hole. The spatial vectors are obtained by Gram-Schmidt orthonormalization
+/// of the r, theta and phi coordinate basis vectors, given as the last three
+/// columns of `jacobian` (see `Metric::spherical_jacobian`).
+pub fn local_frame(g: &Matrix4, g_inv: &Matrix4, jacobian: &Matrix4) -> [Vector4; 4] {
+    let norm = (-g_inv[[0, 0]]).sqrt();
+    observer_frame(g, &(g_inv.row(0) / -norm), jacobian)
+}
+
+/// Orthonormal frame (e_t, e_r, e_theta, e_phi) of the observer of unit
+/// 4-velocity `velocity` = e_t. The spatial vectors are obtained the same way
+/// as in `local_frame`, orthogonally to the observer's world line.
+pub fn observer_frame(g: &Matrix4, velocity: &Vector4, jacobian: &Matrix4) -> [Vector4; 4] {
+    let mut frame = [
+        *velocity,
+        Vector4::zeros(),
+        Vector4::zeros(),
+        Vector4::zeros(),
+    ];
+    for i in 1..4 {
+        let mut vector = jacobian.column(i);
+        for (k, basis_vector) in frame.iter().enumerate().take(i) {
+            let signature = if k == 0 { -1. } else { 1. };
+            let projection = signature * vector.dot(&g.dot(basis_vector));
+            vector -= *basis_vector * projection;
         }
+        let norm = vector.dot(&g.dot(&vector)).sqrt();
+        frame[i] = vector / norm;
     }
-    second_derivative
+    frame
+}
+
+pub(crate) fn unit_vector(theta: f64, phi: f64) -> [f64; 3] {
+    [
+        theta.sin() * phi.cos(),
+        theta.sin() * phi.sin(),
+        theta.cos(),
+    ]
+}
+
+fn rotate(rotation: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+    rotation.map(|row| dot(&row, v))
+}
+
+/// Angular momentum vector r x p of the covariant momentum components
+/// p_theta and p_phi at the angles (theta, phi), in Cartesian components.
+pub(crate) fn angular_momentum(p_theta: f64, p_phi: f64, theta: f64, phi: f64) -> [f64; 3] {
+    let e_theta = [
+        theta.cos() * phi.cos(),
+        theta.cos() * phi.sin(),
+        -theta.sin(),
+    ];
+    let e_phi = [-phi.sin(), phi.cos(), 0.];
+    [0, 1, 2].map(|i| e_phi[i] * p_theta - e_theta[i] * p_phi / theta.sin())
+}
+
+/// Covariant momentum components [p_theta, p_phi] at the angles
+/// (theta, phi) of the angular momentum vector r x p, the inverse of
+/// `angular_momentum`.
+pub(crate) fn angular_components(angular_momentum: &[f64; 3], theta: f64, phi: f64) -> [f64; 2] {
+    let e_theta = [
+        theta.cos() * phi.cos(),
+        theta.cos() * phi.sin(),
+        -theta.sin(),
+    ];
+    let e_phi = [-phi.sin(), phi.cos(), 0.];
+    [
+        dot(angular_momentum, &e_phi),
+        -theta.sin() * dot(angular_momentum, &e_theta),
+    ]
+}
+
+pub(crate) fn dot(u: &[f64; 3], v: &[f64; 3]) -> f64 {
+    u[0] * v[0] + u[1] * v[1] + u[2] * v[2]
+}
+
+pub(crate) fn cross(u: &[f64; 3], v: &[f64; 3]) -> [f64; 3] {
+    [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ]
+}
+
+// Component of u orthogonal to the unit vector n
+fn orthogonal_part(u: &[f64; 3], n: &[f64; 3]) -> [f64; 3] {
+    let projection = dot(u, n);
+    [
+        u[0] - projection * n[0],
+        u[1] - projection * n[1],
+        u[2] - projection * n[2],
+    ]
+}
+
+pub(crate) fn normalized(u: &[f64; 3]) -> [f64; 3] {
+    let norm = dot(u, u).sqrt();
+    u.map(|x| x / norm)
 }
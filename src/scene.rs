@@ -0,0 +1,80 @@
+use crate::{background::Background, observer::Observer, obstacle::Obstacle, tensor::Vector4};
+use image::Rgb;
+
+/// What the rays can see, apart from the spacetime they travel through (see
+/// `Metric`): the obstacles they can hit, the background shown when they
+/// leave the scene, and how the light of the disks is shifted. Tracing only
+/// reads it, so a single scene is shared by all the rendering threads.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Scene {
+    pub obstacles: Vec<Obstacle>,
+    pub background: Background,
+    pub redshift: Redshift,
+}
+
+/// How the motion of the gas of the Ring and AccretionDisk obstacles and
+/// gravity change the light they emit, from the redshift factor g between
+/// the camera and the gas (see `Ray::redshift`), which orbits on the
+/// circular geodesics of the equator plane (see `KEPLERIAN`):
+/// - Off: not at all, the disk looks the same from every direction.
+/// - Specific: the temperature is multiplied by g and the intensity by g³,
+///   which transforms the specific intensity at a given frequency.
+/// - Bolometric: the temperature is multiplied by g and the intensity by g⁴,
+///   which transforms the intensity summed over all the frequencies.
+///
+/// The side of the disk moving towards the camera is then brighter and
+/// bluer than the receding one. Inside the photon sphere, where there is no
+/// circular orbit, the light is not shifted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Redshift {
+    Off,
+    Specific,
+    Bolometric,
+}
+
+impl Default for Redshift {
+    fn default() -> Self {
+        Redshift::Off
+    }
+}
+
+/// Emitters of the light of the disks: the gas on the prograde circular
+/// geodesics.
+pub const KEPLERIAN: Observer = Observer::CircularOrbit { prograde: true };
+
+impl Scene {
+    /// Whether the light of the obstacle is shifted, which needs its
+    /// redshift factor in `color`.
+    pub fn shifts(&self, obstacle: &Obstacle) -> bool {
+        self.redshift != Redshift::Off
+            && matches!(
+                obstacle,
+                Obstacle::Ring { .. } | Obstacle::AccretionDisk { .. }
+            )
+    }
+
+    /// Color of the ray stopped by the obstacle at the given position,
+    /// moving in the given direction (see `escape_direction`): the background
+    /// for the obstacles bounding the scene, the obstacle's own color for the
+    /// others, seen with the redshift factor 'redshift' of `KEPLERIAN`
+    /// emitters there, which is ignored unless the scene `shifts` it.
+    pub fn color(
+        &self,
+        obstacle: &Obstacle,
+        position: &Vector4,
+        direction: &[f64; 2],
+        redshift: f64,
+    ) -> Rgb<f64> {
+        let redshift = if redshift.is_finite() { redshift } else { 1. };
+        match obstacle {
+            Obstacle::MaxDistance { r: _ } | Obstacle::BigBang { t: _ } => {
+                self.background.color(position, direction)
+            }
+            _ => match self.redshift {
+                Redshift::Off => obstacle.color(position),
+                Redshift::Specific => obstacle.shifted_color(position, redshift, 3),
+                Redshift::Bolometric => obstacle.shifted_color(position, redshift, 4),
+            },
+        }
+    }
+}
@@ -1,53 +1,953 @@
-use crate::obstacle::Obstacle;
-use ndarray::{Array1, Array3};
+use crate::{
+    autodiff::{automatic_christoffel, MetricFunction, Scalar},
+    tensor::{Christoffel, Matrix4, Vector4},
+};
+use std::f64::consts::PI;
+use std::fmt::Debug;
 
+/// Coordinate chart in which a metric expects positions and returns its
+/// components. Positions are always 4-vectors with the time coordinate first:
+/// - Spherical: (t, r, theta, phi)
+/// - TwoSidedSpherical: (t, l, theta, phi), where the proper radial distance l
+///   runs over the whole real line and l < 0 is the far side of a throat.
+/// - Cartesian: (t, x, y, z), regular at horizons and on the polar axis. The
+///   camera and the obstacles still work in (t, r, theta, phi), converted by
+///   the metric's `from_spherical` and `to_spherical`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoordinateSystem {
+    Spherical,
+    TwoSidedSpherical,
+    Cartesian,
+}
+
+/// A spacetime geometry. Implementors provide everything the integrator and
+/// the camera need to know about the spacetime, so a new geometry can be
+/// traced without touching `Ray` or `Camera`. A metric is an immutable
+/// description of the spacetime: its components and Christoffel symbols are
+/// evaluated on demand, so one metric is shared by all the traced rays.
+pub trait Metric: Debug + Clone + Send + Sync {
+    /// Covariant components g_{mu nu} at the given position (4x4).
+    fn components(&self, position: &Vector4) -> Matrix4;
+    /// Contravariant components g^{mu nu} at the given position (4x4).
+    fn inverse(&self, position: &Vector4) -> Matrix4;
+    /// Christoffel symbols of the second kind, indexed as [[i, j, k]] for Gamma^i_{jk}.
+    fn christoffel(&self, position: &Vector4) -> Christoffel;
+    /// Radial coordinate of the (outer) event horizon, if there is one.
+    fn horizon_radius(&self) -> Option<f64>;
+    /// Radial coordinate of the inner (Cauchy) horizon, if there is one.
+    fn inner_horizon_radius(&self) -> Option<f64> {
+        None
+    }
+    /// Radial coordinate of the cosmological horizon, beyond which nothing
+    /// can be seen, if there is one.
+    fn cosmological_horizon_radius(&self) -> Option<f64> {
+        None
+    }
+    /// Time coordinate of the big bang, if the spacetime has one. Light
+    /// traced back from the camera reaches it on the particle horizon.
+    fn big_bang_time(&self) -> Option<f64> {
+        None
+    }
+    fn coordinate_system(&self) -> CoordinateSystem;
+    /// Coordinates the metric does not depend on. The matching covariant
+    /// momenta p_mu are constants of motion, such as the energy -p_t of
+    /// stationary spacetimes or the angular momentum p_phi of axisymmetric
+    /// ones.
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [false; 4]
+    }
+    /// Whether the metric is invariant under rotations of (theta, phi), in
+    /// which case each ray is traced in its own orbit plane.
+    fn spherically_symmetric(&self) -> bool {
+        false
+    }
+    /// Position in the metric's coordinates from (t, r, theta, phi).
+    fn from_spherical(&self, position: &Vector4) -> Vector4 {
+        *position
+    }
+    /// Position in (t, r, theta, phi) from the metric's coordinates.
+    fn to_spherical(&self, position: &Vector4) -> Vector4 {
+        *position
+    }
+    /// Jacobian d x^mu / d s^nu of `from_spherical` at the spherical position
+    /// s: its columns are the (t, r, theta, phi) coordinate basis vectors
+    /// expressed in the metric's coordinates.
+    fn spherical_jacobian(&self, _position: &Vector4) -> Matrix4 {
+        Matrix4::eye()
+    }
+    /// Largest affine step the adaptive integration should take from the
+    /// given state, for spacetimes whose own scales the tracer cannot guess.
+    fn step_limit(&self, _position: &Vector4, _position_derivative: &Vector4) -> Option<f64> {
+        None
+    }
+}
+
+/// Flat spacetime in spherical coordinates, where every geodesic is a straight
+/// line. Used as a reference to compare renders with and without gravity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Minkowski;
+
+impl Metric for Minkowski {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        let mut g = Matrix4::zeros();
+        g[[0, 0]] = -1.;
+        g[[1, 1]] = 1.;
+        g[[2, 2]] = position[1].powi(2);
+        g[[3, 3]] = (position[1] * (position[2].sin())).powi(2);
+        g
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        let g = self.components(position);
+        let mut g_inv = Matrix4::zeros();
+        for i in 0..4 {
+            g_inv[[i, i]] = 1. / g[[i, i]];
+        }
+        g_inv
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
+        let r = position[1];
+        let theta = position[2];
+        let mut christoffel = Christoffel::zeros();
+
+        // i = 1 : R
+        christoffel[[1, 2, 2]] = -r;
+        christoffel[[1, 3, 3]] = -r * (theta.sin()).powi(2);
+
+        // i = 2 : Theta
+        christoffel[[2, 1, 2]] = 1. / r;
+        christoffel[[2, 2, 1]] = 1. / r;
+        christoffel[[2, 3, 3]] = -(theta.sin()) * (theta.cos());
+        // i = 3 : Phi
+        christoffel[[3, 1, 3]] = 1. / r;
+        christoffel[[3, 3, 1]] = 1. / r;
+        christoffel[[3, 2, 3]] = 1. / (theta.tan());
+        christoffel[[3, 3, 2]] = 1. / (theta.tan());
+        christoffel
+    }
+
+    fn horizon_radius(&self) -> Option<f64> {
+        None
+    }
+
+    fn spherically_symmetric(&self) -> bool {
+        true
+    }
+
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [true, false, false, true]
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::Spherical
+    }
+}
+
+/// Non-rotating, uncharged black hole of Schwarzschild radius `rs`.
 #[derive(Debug, Clone, PartialEq)]
-pub struct Space {
+pub struct Schwarzschild {
     pub rs: f64,
     pub c: f64,
-    pub christoffel: Array3<f64>,
-    pub obstacles: Vec<Obstacle>,
 }
 
-impl Space {
-    pub fn update_christoffel(&self, position: &Array1<f64>) -> Space {
+impl Metric for Schwarzschild {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        let mut g = Matrix4::zeros();
+        g[[0, 0]] = -(1. - self.rs / position[1]) / (self.c.powi(2));
+        g[[1, 1]] = 1. / (1. - self.rs / position[1]);
+        g[[2, 2]] = position[1].powi(2);
+        g[[3, 3]] = (position[1] * (position[2].sin())).powi(2);
+        g
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        let g = self.components(position);
+        let mut g_inv = Matrix4::zeros();
+        for i in 0..4 {
+            g_inv[[i, i]] = 1. / g[[i, i]];
+        }
+        g_inv
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
         let r = position[1];
         let theta = position[2];
-        let mut updated_space = self.clone();
+        let mut christoffel = Christoffel::zeros();
         let a = 1. / (1. - (self.rs / r));
         let ap = -self.rs / ((r - self.rs).powi(2));
         let b = self.c * self.c * (self.rs / r - 1.);
         let bp = -self.c * self.c * self.rs / (r * r);
 
         // i = 0 : TIME
-        updated_space.christoffel[[0, 0, 1]] = bp / (2. * b);
-        updated_space.christoffel[[0, 1, 0]] = bp / (2. * b);
+        christoffel[[0, 0, 1]] = bp / (2. * b);
+        christoffel[[0, 1, 0]] = bp / (2. * b);
 
         // i = 1 : R
-        updated_space.christoffel[[1, 0, 0]] = -bp / (2. * a);
-        updated_space.christoffel[[1, 1, 1]] = ap / (2. * a);
-        updated_space.christoffel[[1, 2, 2]] = -r / a;
-        updated_space.christoffel[[1, 3, 3]] = -r * ((theta.sin()).powi(2)) / a;
+        christoffel[[1, 0, 0]] = -bp / (2. * a);
+        christoffel[[1, 1, 1]] = ap / (2. * a);
+        christoffel[[1, 2, 2]] = -r / a;
+        christoffel[[1, 3, 3]] = -r * ((theta.sin()).powi(2)) / a;
 
         // i = 2 : Theta
-        updated_space.christoffel[[2, 0, 0]] = 0.;
-        updated_space.christoffel[[2, 1, 2]] = 1. / r;
-        updated_space.christoffel[[2, 2, 1]] = 1. / r;
-        updated_space.christoffel[[2, 3, 3]] = -(theta.sin()) * (theta.cos());
+        christoffel[[2, 0, 0]] = 0.;
+        christoffel[[2, 1, 2]] = 1. / r;
+        christoffel[[2, 2, 1]] = 1. / r;
+        christoffel[[2, 3, 3]] = -(theta.sin()) * (theta.cos());
         // i = 3 : Phi
-        updated_space.christoffel[[3, 1, 3]] = 1. / r;
-        updated_space.christoffel[[3, 3, 1]] = 1. / r;
-        updated_space.christoffel[[3, 2, 3]] = 1. / (theta.tan());
-        updated_space.christoffel[[3, 3, 2]] = 1. / (theta.tan());
-        updated_space
+        christoffel[[3, 1, 3]] = 1. / r;
+        christoffel[[3, 3, 1]] = 1. / r;
+        christoffel[[3, 2, 3]] = 1. / (theta.tan());
+        christoffel[[3, 3, 2]] = 1. / (theta.tan());
+        christoffel
+    }
+
+    fn horizon_radius(&self) -> Option<f64> {
+        Some(self.rs)
+    }
+
+    fn spherically_symmetric(&self) -> bool {
+        true
+    }
+
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [true, false, false, true]
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::Spherical
+    }
+}
+
+impl MetricFunction for Schwarzschild {
+    fn evaluate<T: Scalar>(&self, position: &[T; 4]) -> [[T; 4]; 4] {
+        let r = position[1];
+        let mut g = [[T::from(0.); 4]; 4];
+        g[0][0] = (r.recip() * self.rs - 1.) / self.c.powi(2);
+        g[1][1] = r / (r - self.rs);
+        g[2][2] = r * r;
+        g[3][3] = (r * position[2].sin()).powi(2);
+        g
+    }
+}
+
+/// Rotating, uncharged black hole in Boyer-Lindquist coordinates, with
+/// Schwarzschild radius `rs` (twice the mass) and spin parameter `a`
+/// (angular momentum per unit mass, |a| <= rs / 2), in geometrized units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kerr {
+    pub rs: f64,
+    pub a: f64,
+}
+
+impl Kerr {
+    fn as_kerr_newman(&self) -> KerrNewman {
+        KerrNewman {
+            rs: self.rs,
+            a: self.a,
+            q: 0.,
+        }
+    }
+}
+
+impl Metric for Kerr {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        self.as_kerr_newman().components(position)
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        self.as_kerr_newman().inverse(position)
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
+        self.as_kerr_newman().christoffel(position)
+    }
+
+    fn horizon_radius(&self) -> Option<f64> {
+        self.as_kerr_newman().horizon_radius()
+    }
+
+    fn inner_horizon_radius(&self) -> Option<f64> {
+        self.as_kerr_newman().inner_horizon_radius()
+    }
+
+    fn spherically_symmetric(&self) -> bool {
+        self.a == 0.
+    }
+
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [true, false, false, true]
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::Spherical
+    }
+}
+
+/// Non-rotating black hole with Schwarzschild radius `rs` and charge `q`
+/// (|q| <= rs / 2), in geometrized Gaussian units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReissnerNordstrom {
+    pub rs: f64,
+    pub q: f64,
+}
+
+impl ReissnerNordstrom {
+    fn as_kerr_newman(&self) -> KerrNewman {
+        KerrNewman {
+            rs: self.rs,
+            a: 0.,
+            q: self.q,
+        }
+    }
+}
+
+impl Metric for ReissnerNordstrom {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        self.as_kerr_newman().components(position)
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        self.as_kerr_newman().inverse(position)
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
+        self.as_kerr_newman().christoffel(position)
+    }
+
+    fn horizon_radius(&self) -> Option<f64> {
+        self.as_kerr_newman().horizon_radius()
+    }
+
+    fn inner_horizon_radius(&self) -> Option<f64> {
+        self.as_kerr_newman().inner_horizon_radius()
+    }
+
+    fn spherically_symmetric(&self) -> bool {
+        true
+    }
+
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [true, false, false, true]
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::Spherical
+    }
+}
+
+/// Rotating, charged black hole in Boyer-Lindquist coordinates, with
+/// Schwarzschild radius `rs`, spin parameter `a` and charge `q`
+/// (a^2 + q^2 <= rs^2 / 4), in geometrized Gaussian units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KerrNewman {
+    pub rs: f64,
+    pub a: f64,
+    pub q: f64,
+}
+
+impl KerrNewman {
+    /// Inner and outer horizons, roots of r^2 - rs r + a^2 + q^2 = 0. There
+    /// are none beyond the extremal a^2 + q^2 = rs^2 / 4, around a naked
+    /// singularity.
+    fn horizons(&self) -> Option<(f64, f64)> {
+        let m = self.rs / 2.;
+        let discriminant = m * m - self.a * self.a - self.q * self.q;
+        if discriminant < 0. {
+            return None;
+        }
+        Some((m - discriminant.sqrt(), m + discriminant.sqrt()))
+    }
+}
+
+impl Metric for KerrNewman {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        let r = position[1];
+        let (sin, cos) = position[2].sin_cos();
+        let sigma = r * r + (self.a * cos).powi(2);
+        let delta = r * r - self.rs * r + self.a * self.a + self.q * self.q;
+        let mass_term = self.rs * r - self.q * self.q;
+        let mut g = Matrix4::zeros();
+        g[[0, 0]] = -(1. - mass_term / sigma);
+        g[[0, 3]] = -mass_term * self.a * sin * sin / sigma;
+        g[[3, 0]] = g[[0, 3]];
+        g[[1, 1]] = sigma / delta;
+        g[[2, 2]] = sigma;
+        g[[3, 3]] =
+            (r * r + self.a * self.a + mass_term * (self.a * sin).powi(2) / sigma) * sin * sin;
+        g
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        let r = position[1];
+        let (sin, cos) = position[2].sin_cos();
+        let sigma = r * r + (self.a * cos).powi(2);
+        let delta = r * r - self.rs * r + self.a * self.a + self.q * self.q;
+        let mass_term = self.rs * r - self.q * self.q;
+        let mut g_inv = Matrix4::zeros();
+        g_inv[[0, 0]] =
+            -((r * r + self.a * self.a).powi(2) - (self.a * sin).powi(2) * delta) / (sigma * delta);
+        g_inv[[0, 3]] = -mass_term * self.a / (sigma * delta);
+        g_inv[[3, 0]] = g_inv[[0, 3]];
+        g_inv[[1, 1]] = delta / sigma;
+        g_inv[[2, 2]] = 1. / sigma;
+        g_inv[[3, 3]] = (delta - (self.a * sin).powi(2)) / (sigma * delta * sin * sin);
+        g_inv
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
+        let r = position[1];
+        let (sin, cos) = position[2].sin_cos();
+        let (a, rs) = (self.a, self.rs);
+        let sigma = r * r + (a * cos).powi(2);
+        let sigma_r = 2. * r;
+        let sigma_theta = -2. * a * a * sin * cos;
+        let delta = r * r - rs * r + a * a + self.q * self.q;
+        let delta_r = 2. * r - rs;
+        let mass_term = rs * r - self.q * self.q;
+        // d/dr (mass_term / sigma)
+        let ratio_r = (rs * sigma - mass_term * sigma_r) / sigma.powi(2);
+
+        // The metric only depends on r and theta
+        let mut d_r = Matrix4::zeros();
+        d_r[[0, 0]] = ratio_r;
+        d_r[[0, 3]] = -a * sin * sin * ratio_r;
+        d_r[[3, 0]] = d_r[[0, 3]];
+        d_r[[1, 1]] = (sigma_r * delta - sigma * delta_r) / delta.powi(2);
+        d_r[[2, 2]] = sigma_r;
+        d_r[[3, 3]] = 2. * r * sin * sin + a * a * sin.powi(4) * ratio_r;
+
+        let mut d_theta = Matrix4::zeros();
+        d_theta[[0, 0]] = -mass_term * sigma_theta / sigma.powi(2);
+        d_theta[[0, 3]] =
+            -mass_term * a * (2. * sin * cos * sigma - sin * sin * sigma_theta) / sigma.powi(2);
+        d_theta[[3, 0]] = d_theta[[0, 3]];
+        d_theta[[1, 1]] = sigma_theta / delta;
+        d_theta[[2, 2]] = sigma_theta;
+        d_theta[[3, 3]] = 2. * (r * r + a * a) * sin * cos
+            + mass_term * a * a * (4. * sin.powi(3) * cos * sigma - sin.powi(4) * sigma_theta)
+                / sigma.powi(2);
+
+        let zeros = Matrix4::zeros();
+        christoffel_from_derivatives(&self.inverse(position), &[zeros, d_r, d_theta, zeros])
     }
 
-    pub fn metric(&self, position: &Array1<f64>) -> [f64; 4] {
-        [
-            -(1. - self.rs / position[1]) / (self.c.powi(2)),
-            1. / (1. - self.rs / position[1]),
-            position[1].powi(2),
-            (position[1] * (position[2].sin())).powi(2),
-        ]
+    fn horizon_radius(&self) -> Option<f64> {
+        self.horizons().map(|(_, outer)| outer)
+    }
+
+    fn inner_horizon_radius(&self) -> Option<f64> {
+        self.horizons().map(|(inner, _)| inner)
+    }
+
+    fn spherically_symmetric(&self) -> bool {
+        self.a == 0.
+    }
+
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [true, false, false, true]
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::Spherical
+    }
+}
+
+impl MetricFunction for KerrNewman {
+    fn evaluate<T: Scalar>(&self, position: &[T; 4]) -> [[T; 4]; 4] {
+        let r = position[1];
+        let (sin, cos) = (position[2].sin(), position[2].cos());
+        let sigma = r * r + (cos * self.a).powi(2);
+        let delta = r * r - r * self.rs + (self.a * self.a + self.q * self.q);
+        let mass_term = r * self.rs - self.q * self.q;
+        let mut g = [[T::from(0.); 4]; 4];
+        g[0][0] = mass_term / sigma - 1.;
+        g[0][3] = -(mass_term * sin * sin / sigma) * self.a;
+        g[3][0] = g[0][3];
+        g[1][1] = sigma / delta;
+        g[2][2] = sigma;
+        g[3][3] =
+            (r * r + self.a * self.a + mass_term * (sin * self.a).powi(2) / sigma) * sin * sin;
+        g
+    }
+}
+
+/// Rotating black hole of Schwarzschild radius `rs` and spin parameter `a`
+/// in Cartesian Kerr-Schild coordinates, g = eta + f l l, which are regular
+/// across the horizon and on the axis. The outgoing form is used, regular on
+/// the past horizon that rays traced backwards in time cross. The radial
+/// coordinate r of the spheroids (x^2 + y^2) / (r^2 + a^2) + z^2 / r^2 = 1 is
+/// the Boyer-Lindquist one, so the horizons sit at the same r as for `Kerr`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KerrSchild {
+    pub rs: f64,
+    pub a: f64,
+}
+
+impl KerrSchild {
+    fn as_kerr_newman(&self) -> KerrNewman {
+        KerrNewman {
+            rs: self.rs,
+            a: self.a,
+            q: 0.,
+        }
+    }
+
+    fn spheroidal_radius<T: Scalar>(&self, x: T, y: T, z: T) -> T {
+        let b = x * x + y * y + z * z - self.a * self.a;
+        ((b + (b * b + z * z * (4. * self.a * self.a)).sqrt()) / 2.).sqrt()
+    }
+
+    // Scalar f and covector l_mu of the Kerr-Schild decomposition
+    fn kerr_schild_terms<T: Scalar>(&self, position: &[T; 4]) -> (T, [T; 4]) {
+        let (x, y, z) = (position[1], position[2], position[3]);
+        let r = self.spheroidal_radius(x, y, z);
+        let r2_a2 = r * r + self.a * self.a;
+        let f = r * r * r * self.rs / (r * r * r * r + z * z * (self.a * self.a));
+        let l = [
+            T::from(1.),
+            -(r * x - y * self.a) / r2_a2,
+            -(r * y + x * self.a) / r2_a2,
+            -(z / r),
+        ];
+        (f, l)
+    }
+}
+
+impl MetricFunction for KerrSchild {
+    fn evaluate<T: Scalar>(&self, position: &[T; 4]) -> [[T; 4]; 4] {
+        let (f, l) = self.kerr_schild_terms(position);
+        let mut g = [[T::from(0.); 4]; 4];
+        for (i, row) in g.iter_mut().enumerate() {
+            for (j, component) in row.iter_mut().enumerate() {
+                *component = f * l[i] * l[j];
+            }
+            row[i] = row[i] + if i == 0 { -1. } else { 1. };
+        }
+        g
+    }
+}
+
+impl Metric for KerrSchild {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        Matrix4(self.evaluate(&position.0))
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        // l is null for both eta and g, so g^{mu nu} = eta^{mu nu} - f l^mu l^nu
+        let (f, mut l) = self.kerr_schild_terms(&position.0);
+        l[0] = -l[0];
+        let mut g_inv = Matrix4::from_fn(|i, j| -f * l[i] * l[j]);
+        g_inv[[0, 0]] -= 1.;
+        for i in 1..4 {
+            g_inv[[i, i]] += 1.;
+        }
+        g_inv
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
+        automatic_christoffel(self, position)
+    }
+
+    fn horizon_radius(&self) -> Option<f64> {
+        self.as_kerr_newman().horizon_radius()
+    }
+
+    fn inner_horizon_radius(&self) -> Option<f64> {
+        self.as_kerr_newman().inner_horizon_radius()
+    }
+
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [true, false, false, false]
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::Cartesian
+    }
+
+    fn from_spherical(&self, position: &Vector4) -> Vector4 {
+        let (r, theta, phi) = (position[1], position[2], position[3]);
+        let cylindrical_radius = (r * r + self.a * self.a).sqrt() * theta.sin();
+        Vector4([
+            position[0],
+            cylindrical_radius * phi.cos(),
+            cylindrical_radius * phi.sin(),
+            r * theta.cos(),
+        ])
+    }
+
+    fn to_spherical(&self, position: &Vector4) -> Vector4 {
+        let (x, y, z) = (position[1], position[2], position[3]);
+        let r = self.spheroidal_radius(x, y, z);
+        Vector4([position[0], r, (z / r).clamp(-1., 1.).acos(), y.atan2(x)])
+    }
+
+    fn spherical_jacobian(&self, position: &Vector4) -> Matrix4 {
+        let (r, theta, phi) = (position[1], position[2], position[3]);
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let spheroid_radius = (r * r + self.a * self.a).sqrt();
+        let mut jacobian = Matrix4::zeros();
+        jacobian[[0, 0]] = 1.;
+        jacobian[[1, 1]] = r / spheroid_radius * sin_theta * cos_phi;
+        jacobian[[2, 1]] = r / spheroid_radius * sin_theta * sin_phi;
+        jacobian[[3, 1]] = cos_theta;
+        jacobian[[1, 2]] = spheroid_radius * cos_theta * cos_phi;
+        jacobian[[2, 2]] = spheroid_radius * cos_theta * sin_phi;
+        jacobian[[3, 2]] = -r * sin_theta;
+        jacobian[[1, 3]] = -spheroid_radius * sin_theta * sin_phi;
+        jacobian[[2, 3]] = spheroid_radius * sin_theta * cos_phi;
+        jacobian
+    }
+
+    fn step_limit(&self, position: &Vector4, position_derivative: &Vector4) -> Option<f64> {
+        // Keep each step small compared to the distance to the ring singularity
+        let r = self.spheroidal_radius(position[1], position[2], position[3]);
+        let speed = (position_derivative[1].powi(2)
+            + position_derivative[2].powi(2)
+            + position_derivative[3].powi(2))
+        .sqrt();
+        Some(0.1 * r / speed)
+    }
+}
+
+/// Schwarzschild black hole in a universe with a positive cosmological
+/// constant `lambda` (Kottler metric), with f(r) = 1 - rs / r - lambda r^2 / 3.
+/// The static region lies between the black hole and the cosmological
+/// horizons, which requires 0 < lambda < 4 / (9 rs^2).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchwarzschildDeSitter {
+    pub rs: f64,
+    pub lambda: f64,
+}
+
+impl SchwarzschildDeSitter {
+    /// Positive roots of f(r) = 0, i.e. of r^3 - 3 r / lambda + 3 rs / lambda,
+    /// in increasing order: black hole horizon then cosmological horizon.
+    fn horizons(&self) -> Option<(f64, f64)> {
+        if self.rs <= 0. {
+            return Some((0., (3. / self.lambda).sqrt()));
+        }
+        let p = -3. / self.lambda;
+        let q = 3. * self.rs / self.lambda;
+        let cos_argument = 3. * q / (2. * p) * (-3. / p).sqrt();
+        if !(-1. ..=1.).contains(&cos_argument) {
+            return None;
+        }
+        let amplitude = 2. * (-p / 3.).sqrt();
+        let angle = cos_argument.acos() / 3.;
+        let cosmological = amplitude * angle.cos();
+        let black_hole = amplitude * (angle - 2. * PI / 3.).cos();
+        Some((black_hole, cosmological))
+    }
+}
+
+impl Metric for SchwarzschildDeSitter {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        let r = position[1];
+        let f = 1. - self.rs / r - self.lambda * r * r / 3.;
+        let mut g = Matrix4::zeros();
+        g[[0, 0]] = -f;
+        g[[1, 1]] = 1. / f;
+        g[[2, 2]] = r.powi(2);
+        g[[3, 3]] = (r * (position[2].sin())).powi(2);
+        g
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        let g = self.components(position);
+        let mut g_inv = Matrix4::zeros();
+        for i in 0..4 {
+            g_inv[[i, i]] = 1. / g[[i, i]];
+        }
+        g_inv
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
+        let r = position[1];
+        let theta = position[2];
+        let f = 1. - self.rs / r - self.lambda * r * r / 3.;
+        let fp = self.rs / (r * r) - 2. * self.lambda * r / 3.;
+        let mut christoffel = Christoffel::zeros();
+
+        // i = 0 : TIME
+        christoffel[[0, 0, 1]] = fp / (2. * f);
+        christoffel[[0, 1, 0]] = fp / (2. * f);
+
+        // i = 1 : R
+        christoffel[[1, 0, 0]] = f * fp / 2.;
+        christoffel[[1, 1, 1]] = -fp / (2. * f);
+        christoffel[[1, 2, 2]] = -r * f;
+        christoffel[[1, 3, 3]] = -r * f * (theta.sin()).powi(2);
+
+        // i = 2 : Theta
+        christoffel[[2, 1, 2]] = 1. / r;
+        christoffel[[2, 2, 1]] = 1. / r;
+        christoffel[[2, 3, 3]] = -(theta.sin()) * (theta.cos());
+        // i = 3 : Phi
+        christoffel[[3, 1, 3]] = 1. / r;
+        christoffel[[3, 3, 1]] = 1. / r;
+        christoffel[[3, 2, 3]] = 1. / (theta.tan());
+        christoffel[[3, 3, 2]] = 1. / (theta.tan());
+        christoffel
+    }
+
+    fn horizon_radius(&self) -> Option<f64> {
+        self.horizons()
+            .map(|(black_hole, _)| black_hole)
+            .filter(|r| *r > 0.)
+    }
+
+    fn cosmological_horizon_radius(&self) -> Option<f64> {
+        self.horizons().map(|(_, cosmological)| cosmological)
+    }
+
+    fn spherically_symmetric(&self) -> bool {
+        true
+    }
+
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [true, false, false, true]
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::Spherical
+    }
+}
+
+/// Spatially flat, expanding FLRW universe in comoving spherical coordinates,
+/// ds^2 = -dt^2 + a(t)^2 (dr^2 + r^2 dOmega^2). The universe is filled with a
+/// perfect fluid of equation of state `w` > -1/3 (0 for matter, 1/3 for
+/// radiation), so a(t) = (1 + hubble_constant t / n)^n with n = 2 / (3 (1 + w)),
+/// normalized to a = 1 and da/dt = hubble_constant at t = 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flrw {
+    pub hubble_constant: f64,
+    pub w: f64,
+}
+
+impl Flrw {
+    fn exponent(&self) -> f64 {
+        2. / (3. * (1. + self.w))
+    }
+
+    /// Scale factor a(t). The redshift of light emitted at time t and
+    /// received at t = 0 is 1 + z = 1 / a(t).
+    pub fn scale_factor(&self, t: f64) -> f64 {
+        let n = self.exponent();
+        (1. + self.hubble_constant * t / n).powf(n)
+    }
+
+    /// Time derivative of the scale factor.
+    pub fn scale_factor_derivative(&self, t: f64) -> f64 {
+        let n = self.exponent();
+        self.hubble_constant * (1. + self.hubble_constant * t / n).powf(n - 1.)
+    }
+}
+
+impl Metric for Flrw {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        let a2 = self.scale_factor(position[0]).powi(2);
+        let mut g = Matrix4::zeros();
+        g[[0, 0]] = -1.;
+        g[[1, 1]] = a2;
+        g[[2, 2]] = a2 * position[1].powi(2);
+        g[[3, 3]] = a2 * (position[1] * (position[2].sin())).powi(2);
+        g
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        let g = self.components(position);
+        let mut g_inv = Matrix4::zeros();
+        for i in 0..4 {
+            g_inv[[i, i]] = 1. / g[[i, i]];
+        }
+        g_inv
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
+        let r = position[1];
+        let theta = position[2];
+        let a = self.scale_factor(position[0]);
+        let ap = self.scale_factor_derivative(position[0]);
+        let mut christoffel = Christoffel::zeros();
+
+        // i = 0 : TIME
+        christoffel[[0, 1, 1]] = a * ap;
+        christoffel[[0, 2, 2]] = a * ap * r * r;
+        christoffel[[0, 3, 3]] = a * ap * (r * theta.sin()).powi(2);
+
+        // i = 1 : R
+        christoffel[[1, 0, 1]] = ap / a;
+        christoffel[[1, 1, 0]] = ap / a;
+        christoffel[[1, 2, 2]] = -r;
+        christoffel[[1, 3, 3]] = -r * (theta.sin()).powi(2);
+
+        // i = 2 : Theta
+        christoffel[[2, 0, 2]] = ap / a;
+        christoffel[[2, 2, 0]] = ap / a;
+        christoffel[[2, 1, 2]] = 1. / r;
+        christoffel[[2, 2, 1]] = 1. / r;
+        christoffel[[2, 3, 3]] = -(theta.sin()) * (theta.cos());
+        // i = 3 : Phi
+        christoffel[[3, 0, 3]] = ap / a;
+        christoffel[[3, 3, 0]] = ap / a;
+        christoffel[[3, 1, 3]] = 1. / r;
+        christoffel[[3, 3, 1]] = 1. / r;
+        christoffel[[3, 2, 3]] = 1. / (theta.tan());
+        christoffel[[3, 3, 2]] = 1. / (theta.tan());
+        christoffel
+    }
+
+    fn horizon_radius(&self) -> Option<f64> {
+        None
+    }
+
+    fn big_bang_time(&self) -> Option<f64> {
+        Some(-self.exponent() / self.hubble_constant)
+    }
+
+    fn spherically_symmetric(&self) -> bool {
+        true
+    }
+
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [false, false, false, true]
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::Spherical
+    }
+
+    /// Steps of at most a tenth of the Hubble time a / (da/dt) at the current
+    /// epoch, which shrinks to zero towards the big bang.
+    fn step_limit(&self, position: &Vector4, position_derivative: &Vector4) -> Option<f64> {
+        let hubble_time =
+            self.scale_factor(position[0]) / self.scale_factor_derivative(position[0]);
+        Some(0.1 * hubble_time / position_derivative[0].abs())
+    }
+}
+
+/// Ellis wormhole: the Morris-Thorne traversable wormhole with no tidal
+/// forces, ds^2 = -dt^2 + dl^2 + (b0^2 + l^2) dOmega^2, where `throat_radius`
+/// is b0. Both sides of the throat are asymptotically flat.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EllisWormhole {
+    pub throat_radius: f64,
+}
+
+impl Metric for EllisWormhole {
+    fn components(&self, position: &Vector4) -> Matrix4 {
+        let r2 = self.throat_radius.powi(2) + position[1].powi(2);
+        let mut g = Matrix4::zeros();
+        g[[0, 0]] = -1.;
+        g[[1, 1]] = 1.;
+        g[[2, 2]] = r2;
+        g[[3, 3]] = r2 * (position[2].sin()).powi(2);
+        g
+    }
+
+    fn inverse(&self, position: &Vector4) -> Matrix4 {
+        let g = self.components(position);
+        let mut g_inv = Matrix4::zeros();
+        for i in 0..4 {
+            g_inv[[i, i]] = 1. / g[[i, i]];
+        }
+        g_inv
+    }
+
+    fn christoffel(&self, position: &Vector4) -> Christoffel {
+        let l = position[1];
+        let theta = position[2];
+        let r2 = self.throat_radius.powi(2) + l * l;
+        let mut christoffel = Christoffel::zeros();
+
+        // i = 1 : L
+        christoffel[[1, 2, 2]] = -l;
+        christoffel[[1, 3, 3]] = -l * (theta.sin()).powi(2);
+
+        // i = 2 : Theta
+        christoffel[[2, 1, 2]] = l / r2;
+        christoffel[[2, 2, 1]] = l / r2;
+        christoffel[[2, 3, 3]] = -(theta.sin()) * (theta.cos());
+        // i = 3 : Phi
+        christoffel[[3, 1, 3]] = l / r2;
+        christoffel[[3, 3, 1]] = l / r2;
+        christoffel[[3, 2, 3]] = 1. / (theta.tan());
+        christoffel[[3, 3, 2]] = 1. / (theta.tan());
+        christoffel
+    }
+
+    fn horizon_radius(&self) -> Option<f64> {
+        None
+    }
+
+    fn spherically_symmetric(&self) -> bool {
+        true
+    }
+
+    fn cyclic_coordinates(&self) -> [bool; 4] {
+        [true, false, false, true]
+    }
+
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::TwoSidedSpherical
+    }
+}
+
+/// Christoffel symbols Gamma^i_{jk} = 1/2 g^{il} (d_j g_{lk} + d_k g_{lj} - d_l g_{jk}),
+/// from the inverse metric and the partial derivatives of the metric, where
+/// `metric_derivatives[l]` holds d g_{mu nu} / d x^l.
+pub fn christoffel_from_derivatives(
+    g_inv: &Matrix4,
+    metric_derivatives: &[Matrix4; 4],
+) -> Christoffel {
+    let mut christoffel = Christoffel::zeros();
+    for i in 0..4 {
+        for j in 0..4 {
+            for k in j..4 {
+                let mut value = 0.;
+                for l in 0..4 {
+                    value += g_inv[[i, l]]
+                        * (metric_derivatives[j][[l, k]] + metric_derivatives[k][[l, j]]
+                            - metric_derivatives[l][[j, k]]);
+                }
+                christoffel[[i, j, k]] = value / 2.;
+                christoffel[[i, k, j]] = value / 2.;
+            }
+        }
+    }
+    christoffel
+}
+
+/// Inverse of a (non-degenerate) 4x4 metric by Gauss-Jordan elimination with
+/// partial pivoting, for metrics without a closed form inverse.
+pub fn invert_metric(g: &Matrix4) -> Matrix4 {
+    let mut a = *g;
+    let mut g_inv = Matrix4::eye();
+    for column in 0..4 {
+        let mut pivot = column;
+        for row in column + 1..4 {
+            if a[[row, column]].abs() > a[[pivot, column]].abs() {
+                pivot = row;
+            }
+        }
+        a.0.swap(column, pivot);
+        g_inv.0.swap(column, pivot);
+        let scale = 1. / a[[column, column]];
+        for k in 0..4 {
+            a[[column, k]] *= scale;
+            g_inv[[column, k]] *= scale;
+        }
+        for row in 0..4 {
+            if row != column {
+                let factor = a[[row, column]];
+                for k in 0..4 {
+                    a[[row, k]] -= factor * a[[column, k]];
+                    g_inv[[row, k]] -= factor * g_inv[[column, k]];
+                }
+            }
+        }
     }
+    g_inv
 }
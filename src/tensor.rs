@@ -0,0 +1,185 @@
+use std::ops::{Add, AddAssign, Div, Index, IndexMut, Mul, Neg, Sub, SubAssign};
+
+/// 4-vector, such as a position (t, r, theta, phi), a velocity or a covariant
+/// momentum. Vectors, metrics and Christoffel symbols are plain fixed-size
+/// arrays stored on the stack, so that the integration of a ray does not
+/// allocate. They are indexed like ndarray's arrays: `v[i]`, `g[[i, j]]` and
+/// `christoffel[[i, j, k]]`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Vector4(pub [f64; 4]);
+
+/// 4x4 matrix, such as the metric components g_{mu nu} or their inverse.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Matrix4(pub [[f64; 4]; 4]);
+
+/// Christoffel symbols of the second kind Gamma^i_{jk}, indexed as [[i, j, k]].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Christoffel(pub [[[f64; 4]; 4]; 4]);
+
+impl Vector4 {
+    pub fn zeros() -> Self {
+        Vector4([0.; 4])
+    }
+
+    pub fn dot(&self, other: &Vector4) -> f64 {
+        self.0.iter().zip(other.0.iter()).map(|(x, y)| x * y).sum()
+    }
+
+    /// Largest absolute value of the components.
+    pub fn max_norm(&self) -> f64 {
+        self.0.iter().fold(0., |norm: f64, x| norm.max(x.abs()))
+    }
+}
+
+impl Matrix4 {
+    pub fn zeros() -> Self {
+        Matrix4([[0.; 4]; 4])
+    }
+
+    pub fn eye() -> Self {
+        Matrix4::from_fn(|i, j| if i == j { 1. } else { 0. })
+    }
+
+    pub fn from_fn<F: Fn(usize, usize) -> f64>(f: F) -> Self {
+        let mut matrix = Matrix4::zeros();
+        for (i, row) in matrix.0.iter_mut().enumerate() {
+            for (j, component) in row.iter_mut().enumerate() {
+                *component = f(i, j);
+            }
+        }
+        matrix
+    }
+
+    pub fn row(&self, i: usize) -> Vector4 {
+        Vector4(self.0[i])
+    }
+
+    pub fn column(&self, j: usize) -> Vector4 {
+        Vector4([self.0[0][j], self.0[1][j], self.0[2][j], self.0[3][j]])
+    }
+
+    /// Matrix-vector product, e.g. lowering an index with the metric.
+    pub fn dot(&self, vector: &Vector4) -> Vector4 {
+        Vector4([
+            self.row(0).dot(vector),
+            self.row(1).dot(vector),
+            self.row(2).dot(vector),
+            self.row(3).dot(vector),
+        ])
+    }
+
+    pub fn matrix_product(&self, other: &Matrix4) -> Matrix4 {
+        Matrix4::from_fn(|i, j| self.row(i).dot(&other.column(j)))
+    }
+}
+
+impl Christoffel {
+    pub fn zeros() -> Self {
+        Christoffel([[[0.; 4]; 4]; 4])
+    }
+}
+
+impl From<[f64; 4]> for Vector4 {
+    fn from(components: [f64; 4]) -> Self {
+        Vector4(components)
+    }
+}
+
+impl Index<usize> for Vector4 {
+    type Output = f64;
+    fn index(&self, i: usize) -> &f64 {
+        &self.0[i]
+    }
+}
+
+impl IndexMut<usize> for Vector4 {
+    fn index_mut(&mut self, i: usize) -> &mut f64 {
+        &mut self.0[i]
+    }
+}
+
+impl Index<[usize; 2]> for Matrix4 {
+    type Output = f64;
+    fn index(&self, [i, j]: [usize; 2]) -> &f64 {
+        &self.0[i][j]
+    }
+}
+
+impl IndexMut<[usize; 2]> for Matrix4 {
+    fn index_mut(&mut self, [i, j]: [usize; 2]) -> &mut f64 {
+        &mut self.0[i][j]
+    }
+}
+
+impl Index<[usize; 3]> for Christoffel {
+    type Output = f64;
+    fn index(&self, [i, j, k]: [usize; 3]) -> &f64 {
+        &self.0[i][j][k]
+    }
+}
+
+impl IndexMut<[usize; 3]> for Christoffel {
+    fn index_mut(&mut self, [i, j, k]: [usize; 3]) -> &mut f64 {
+        &mut self.0[i][j][k]
+    }
+}
+
+impl Add for Vector4 {
+    type Output = Vector4;
+    fn add(mut self, other: Vector4) -> Vector4 {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for Vector4 {
+    fn add_assign(&mut self, other: Vector4) {
+        for (x, y) in self.0.iter_mut().zip(other.0) {
+            *x += y;
+        }
+    }
+}
+
+impl Sub for Vector4 {
+    type Output = Vector4;
+    fn sub(mut self, other: Vector4) -> Vector4 {
+        self -= other;
+        self
+    }
+}
+
+impl SubAssign for Vector4 {
+    fn sub_assign(&mut self, other: Vector4) {
+        for (x, y) in self.0.iter_mut().zip(other.0) {
+            *x -= y;
+        }
+    }
+}
+
+impl Neg for Vector4 {
+    type Output = Vector4;
+    fn neg(self) -> Vector4 {
+        self * -1.
+    }
+}
+
+impl Mul<f64> for Vector4 {
+    type Output = Vector4;
+    fn mul(self, scale: f64) -> Vector4 {
+        Vector4(self.0.map(|x| x * scale))
+    }
+}
+
+impl Mul<Vector4> for f64 {
+    type Output = Vector4;
+    fn mul(self, vector: Vector4) -> Vector4 {
+        vector * self
+    }
+}
+
+impl Div<f64> for Vector4 {
+    type Output = Vector4;
+    fn div(self, scale: f64) -> Vector4 {
+        Vector4(self.0.map(|x| x / scale))
+    }
+}
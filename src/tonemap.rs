@@ -0,0 +1,119 @@
+use image::Rgb;
+
+/// How the linear radiance of a `Framebuffer` becomes 8-bit colors: it is
+/// multiplied by the scale given by the exposure, compressed into [0, 1] by
+/// the operator, then raised to 'gamma'.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToneMapping {
+    pub operator: Operator,
+    pub exposure: Exposure,
+    pub gamma: f64,
+}
+
+/// Compression of a scaled channel x into [0, 1]:
+/// - Linear: clamped, min(x, 1).
+/// - Reinhard: x / (1 + x).
+/// - Aces: the filmic curve of the Academy Color Encoding System, as fitted
+///   by Narkowicz, with a toe in the shadows and a shoulder in the highlights.
+/// - Logarithmic: ln(1 + x) / ln(1 + white), white being the brightest scaled
+///   channel of the frame, which keeps the faint lensed rings visible next to
+///   the inner edge of the disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Linear,
+    Reinhard,
+    Aces,
+    Logarithmic,
+}
+
+/// Scale applied to the radiance before the operator:
+/// - Brightest(e): e divided by the brightest channel of the frame. A single
+///   hot pixel darkens the whole frame.
+/// - Fixed(s): s, the same for every frame of an animation.
+/// - Percentile { percentile, key }: the luminance of the pixels which are
+///   not black at the given percentile, between 0 and 1, is mapped to 'key'.
+///   Pixels brighter than it, such as fireflies, do not change the exposure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exposure {
+    Brightest(f64),
+    Fixed(f64),
+    Percentile { percentile: f64, key: f64 },
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            operator: Operator::Linear,
+            exposure: Exposure::Brightest(2.5),
+            gamma: 0.75,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// 8-bit colors of the pixels of linear radiance 'hdr'.
+    pub fn apply(&self, hdr: &[Rgb<f64>]) -> Vec<Rgb<u8>> {
+        let scale = self.exposure.scale(hdr);
+        let white = scale * brightest(hdr);
+        hdr.iter()
+            .map(|pixel| {
+                Rgb(pixel.0.map(|channel| {
+                    let x = scale * channel;
+                    let compressed = match self.operator {
+                        Operator::Linear => x.min(1.),
+                        Operator::Reinhard => x / (1. + x),
+                        Operator::Aces => {
+                            (x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0., 1.)
+                        }
+                        // A black frame stays black
+                        Operator::Logarithmic if white > 0. => x.ln_1p() / white.ln_1p(),
+                        Operator::Logarithmic => 0.,
+                    };
+                    (compressed.powf(self.gamma) * 255.).min(255.) as u8
+                }))
+            })
+            .collect()
+    }
+}
+
+impl Exposure {
+    /// Factor by which the radiance of the pixels 'hdr' is multiplied. Use
+    /// it as a `Fixed` exposure to keep the exposure of a frame in the
+    /// following ones.
+    pub fn scale(&self, hdr: &[Rgb<f64>]) -> f64 {
+        match *self {
+            Exposure::Brightest(exposition) => {
+                let brightest = brightest(hdr);
+                if brightest <= 0. {
+                    return 0.;
+                }
+                exposition / brightest
+            }
+            Exposure::Fixed(scale) => scale,
+            Exposure::Percentile { percentile, key } => {
+                let mut luminances: Vec<f64> = hdr
+                    .iter()
+                    .map(luminance)
+                    .filter(|luminance| *luminance > 0.)
+                    .collect();
+                if luminances.is_empty() {
+                    return 0.;
+                }
+                luminances.sort_by(|a, b| a.partial_cmp(b).expect("Luminances are not NaN"));
+                let index = (percentile.clamp(0., 1.) * (luminances.len() - 1) as f64).round();
+                key / luminances[index as usize]
+            }
+        }
+    }
+}
+
+/// Relative luminance of a linear Rec. 709 color.
+pub fn luminance(pixel: &Rgb<f64>) -> f64 {
+    0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]
+}
+
+fn brightest(hdr: &[Rgb<f64>]) -> f64 {
+    hdr.iter()
+        .map(|pixel| pixel[0].max(pixel[1]).max(pixel[2]))
+        .fold(0., f64::max)
+}